@@ -0,0 +1,81 @@
+//! Throughput of the decisions cache under concurrent lookups and inserts.
+//!
+//! A cache with a single shard behaves like the former `Mutex`-guarded cache,
+//! and is used as the baseline.
+
+use appguard_client_authentication::{Cache, CacheConfig, CacheKey};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use nullnet_libappguard::appguard_commands::{FirewallDefaults, FirewallPolicy};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const OPERATIONS_PER_THREAD: usize = 10_000;
+const DISTINCT_KEYS: usize = 2_000;
+
+fn cache_key(i: usize) -> CacheKey {
+    CacheKey {
+        source_ip: Some(format!("10.0.{}.{}", i / 256 % 256, i % 256)),
+        path: Some(format!("/resource/{}", i % 50)),
+        method: Some("GET".to_string()),
+        ..CacheKey::default()
+    }
+}
+
+fn active_cache(shards: usize) -> Arc<Cache> {
+    let cache = Cache::new(CacheConfig {
+        shards,
+        ..CacheConfig::default()
+    });
+    cache.reset(FirewallDefaults {
+        timeout: 1000,
+        policy: FirewallPolicy::Allow as i32,
+        cache: true,
+    });
+    Arc::new(cache)
+}
+
+/// Runs `threads` workers, each looking up keys and inserting the missing ones.
+fn run(cache: &Arc<Cache>, threads: usize, keys: &Arc<Vec<CacheKey>>) -> Duration {
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for t in 0..threads {
+            let cache = cache.clone();
+            let keys = keys.clone();
+            scope.spawn(move || {
+                for i in 0..OPERATIONS_PER_THREAD {
+                    let key = &keys[(i * 7 + t * 13) % keys.len()];
+                    if cache.get(key).is_none() {
+                        cache.insert(key.clone(), FirewallPolicy::Allow);
+                    }
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn concurrent_access(c: &mut Criterion) {
+    let keys = Arc::new((0..DISTINCT_KEYS).map(cache_key).collect::<Vec<_>>());
+    let mut group = c.benchmark_group("cache_concurrent_access");
+
+    for threads in [1, 4, 16] {
+        group.throughput(Throughput::Elements(
+            (threads * OPERATIONS_PER_THREAD) as u64,
+        ));
+        for shards in [1, CacheConfig::default().shards] {
+            let cache = active_cache(shards);
+            group.bench_with_input(
+                BenchmarkId::new(format!("{shards}_shards"), threads),
+                &threads,
+                |b, &threads| {
+                    b.iter_custom(|iters| (0..iters).map(|_| run(&cache, threads, &keys)).sum());
+                },
+            );
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_access);
+criterion_main!(benches);
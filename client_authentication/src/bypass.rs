@@ -0,0 +1,161 @@
+use crate::client_ip::parse_cidr;
+use globset::{GlobBuilder, GlobMatcher};
+use ipnet::IpNet;
+use nullnet_liberror::{Error, ErrorHandler, Location, location};
+use regex::Regex;
+use std::net::IpAddr;
+
+/// What happens to the requests matched by a [`BypassRule`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BypassAction {
+    /// Requests are forwarded without being inspected nor reported to the server.
+    Skip,
+    /// Requests are inspected and reported as usual, but never blocked (as in monitor-only mode).
+    Monitor,
+}
+
+/// Request attributes the bypass rules are matched against.
+pub struct BypassRequest<'a> {
+    pub source_ip: Option<&'a str>,
+    pub host: Option<&'a str>,
+    pub path: &'a str,
+    pub method: &'a str,
+}
+
+/// Pattern matched against the request path.
+#[derive(Debug, Clone)]
+enum PathPattern {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl PathPattern {
+    fn is_match(&self, path: &str) -> bool {
+        match self {
+            PathPattern::Glob(glob) => glob.is_match(path),
+            PathPattern::Regex(regex) => regex.is_match(path),
+        }
+    }
+}
+
+/// Local rule exempting some requests (e.g. health checks, metrics or static assets) from the firewall.
+///
+/// A request matches the rule if it matches at least one of the values of each attribute
+/// the rule constrains; a rule without constraints matches every request.
+#[derive(Debug, Clone)]
+pub struct BypassRule {
+    action: BypassAction,
+    paths: Vec<PathPattern>,
+    methods: Vec<String>,
+    hosts: Vec<String>,
+    source_cidrs: Vec<IpNet>,
+}
+
+impl BypassRule {
+    /// The matching requests are not inspected at all.
+    #[must_use]
+    pub fn skip() -> Self {
+        Self::new(BypassAction::Skip)
+    }
+
+    /// The matching requests are inspected, but never blocked.
+    #[must_use]
+    pub fn monitor() -> Self {
+        Self::new(BypassAction::Monitor)
+    }
+
+    fn new(action: BypassAction) -> Self {
+        Self {
+            action,
+            paths: Vec::new(),
+            methods: Vec::new(),
+            hosts: Vec::new(),
+            source_cidrs: Vec::new(),
+        }
+    }
+
+    /// Matches the paths matching a glob pattern, where `*` doesn't cross `/` and `**` does
+    /// (e.g. `/static/**`).
+    #[allow(clippy::missing_errors_doc)]
+    pub fn path_glob(mut self, pattern: &str) -> Result<Self, Error> {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .handle_err(location!())?;
+        self.paths.push(PathPattern::Glob(glob.compile_matcher()));
+        Ok(self)
+    }
+
+    /// Matches the paths matching a regular expression (e.g. `^/health$`).
+    #[allow(clippy::missing_errors_doc)]
+    pub fn path_regex(mut self, pattern: &str) -> Result<Self, Error> {
+        let regex = Regex::new(pattern).handle_err(location!())?;
+        self.paths.push(PathPattern::Regex(regex));
+        Ok(self)
+    }
+
+    /// Matches a method (case-insensitive).
+    #[must_use]
+    pub fn method(mut self, method: impl Into<String>) -> Self {
+        self.methods.push(method.into());
+        self
+    }
+
+    /// Matches a host, as sent by the client without the port (case-insensitive).
+    ///
+    /// The host is chosen by the client, which can send any value to be bypassed:
+    /// pair it with [`source_cidr`](Self::source_cidr) on [`skip`](Self::skip) rules.
+    #[must_use]
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.hosts.push(host.into());
+        self
+    }
+
+    /// Matches the source IPs in a CIDR block (e.g. `10.0.0.0/8`); a single IP is also accepted.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn source_cidr(mut self, cidr: &str) -> Result<Self, Error> {
+        self.source_cidrs.push(parse_cidr(cidr)?);
+        Ok(self)
+    }
+
+    /// Returns the action of this rule.
+    #[must_use]
+    pub fn action(&self) -> BypassAction {
+        self.action
+    }
+
+    /// Returns `true` if the request matches this rule.
+    #[must_use]
+    pub fn matches(&self, request: &BypassRequest) -> bool {
+        let path_matches =
+            self.paths.is_empty() || self.paths.iter().any(|p| p.is_match(request.path));
+        let method_matches = self.methods.is_empty()
+            || self
+                .methods
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(request.method));
+        let host_matches = self.hosts.is_empty()
+            || request.host.is_some_and(|host| {
+                let host = strip_port(host);
+                self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+            });
+        let source_matches = self.source_cidrs.is_empty()
+            || request
+                .source_ip
+                .and_then(|ip| ip.parse::<IpAddr>().ok())
+                .is_some_and(|ip| self.source_cidrs.iter().any(|net| net.contains(&ip)));
+
+        path_matches && method_matches && host_matches && source_matches
+    }
+}
+
+/// Removes the port from a `Host` header value (`example.com:8080`, `[::1]:8080`).
+fn strip_port(host: &str) -> &str {
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
@@ -1,41 +1,378 @@
+use crate::cache_key::{CacheKey, CacheKeyPolicy};
+use crate::cache_snapshot::{CacheSnapshot, CacheSnapshotConfig};
+use lru::LruCache;
 use nullnet_libappguard::appguard_commands::{FirewallDefaults, FirewallPolicy};
-use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, RandomState};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError, Weak};
+use std::time::{Duration, Instant, SystemTime};
 
+/// Settings of the firewall decisions cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// How long an `Allow` decision is kept (a zero duration disables caching `Allow` decisions).
+    pub allow_ttl: Duration,
+    /// How long a `Deny` decision is kept (a zero duration disables caching `Deny` decisions).
+    pub deny_ttl: Duration,
+    /// Maximum number of entries; the least recently used ones are evicted first.
+    pub capacity: usize,
+    /// Number of independently locked partitions the entries are spread across.
+    pub shards: usize,
+    /// How often expired entries are removed
+    /// (a zero duration disables the sweep: expired entries are then removed when looked up or evicted).
+    pub sweep_interval: Duration,
+    /// Request attributes the cached decisions depend on.
+    pub key_policy: CacheKeyPolicy,
+    /// Persists the decisions across restarts (disabled by default).
+    pub snapshot: Option<CacheSnapshotConfig>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            allow_ttl: Duration::from_secs(60),
+            deny_ttl: Duration::from_secs(300),
+            capacity: 10_000,
+            shards: 16,
+            sweep_interval: Duration::from_secs(30),
+            key_policy: CacheKeyPolicy::default(),
+            snapshot: None,
+        }
+    }
+}
+
+/// Selects the cache entries to drop.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheInvalidation {
+    /// Every entry.
+    All,
+    /// Entries that may apply to this source IP.
+    ///
+    /// This includes entries whose key doesn't depend on the source IP.
+    SourceIp(String),
+    /// Entries that may apply to paths starting with this prefix.
+    ///
+    /// This includes entries whose key doesn't depend on the path.
+    PathPrefix(String),
+    /// Entries holding this decision.
+    Policy(FirewallPolicy),
+}
+
+impl CacheInvalidation {
+    fn matches(&self, key: &CacheKey, policy: FirewallPolicy) -> bool {
+        match self {
+            CacheInvalidation::All => true,
+            CacheInvalidation::SourceIp(ip) => key.source_ip.as_ref().is_none_or(|k| k == ip),
+            CacheInvalidation::PathPrefix(prefix) => key
+                .path
+                .as_ref()
+                .is_none_or(|p| p.starts_with(prefix.as_str())),
+            CacheInvalidation::Policy(p) => policy == *p,
+        }
+    }
+}
+
+struct CacheEntry {
+    policy: FirewallPolicy,
+    expires_at: Instant,
+}
+
+/// Counters and current size of the cache.
+///
+/// Counters are cumulative since the client started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// Lookups answered by the cache.
+    pub hits: u64,
+    /// Lookups that had to be forwarded to the server.
+    pub misses: u64,
+    /// Decisions added to the cache.
+    pub inserts: u64,
+    /// Entries evicted to make room for new ones.
+    pub evictions: u64,
+    /// Entries removed because their TTL elapsed.
+    pub expirations: u64,
+    /// Current number of `Allow` entries.
+    pub allow_entries: usize,
+    /// Current number of `Deny` entries.
+    pub deny_entries: usize,
+}
+
+/// A decision currently held by the cache.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedDecision {
+    pub key: CacheKey,
+    pub policy: FirewallPolicy,
+    /// Time left before the entry expires.
+    pub expires_in: Duration,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+impl Counters {
+    fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+type Shard = LruCache<CacheKey, CacheEntry>;
+
+/// Concurrent cache of firewall decisions.
+///
+/// Entries are spread across several shards, each guarded by its own short-lived lock,
+/// so that concurrent requests rarely contend with each other;
+/// the capacity is split evenly among the shards.
 pub struct Cache {
-    active: bool,
-    entries: HashMap<CacheKey, FirewallPolicy>,
+    active: AtomicBool,
+    defaults: Mutex<Option<FirewallDefaults>>,
+    pending_snapshot: Mutex<Option<CacheSnapshot>>,
+    config: CacheConfig,
+    hasher: RandomState,
+    shards: Box<[Mutex<Shard>]>,
+    counters: Counters,
 }
 
 impl Cache {
-    pub(crate) fn new(defaults: FirewallDefaults) -> Cache {
+    /// Creates an inactive cache; it's activated by the firewall defaults passed to [`Cache::reset`].
+    #[must_use]
+    pub fn new(config: CacheConfig) -> Cache {
+        let shards = config.shards.max(1);
+        let shard_capacity = config.capacity.div_ceil(shards);
+        let shard_capacity = NonZeroUsize::new(shard_capacity).unwrap_or(NonZeroUsize::MIN);
         Self {
-            active: defaults.cache,
-            entries: HashMap::new(),
+            active: AtomicBool::new(false),
+            defaults: Mutex::new(None),
+            pending_snapshot: Mutex::new(None),
+            config,
+            hasher: RandomState::new(),
+            shards: (0..shards)
+                .map(|_| Mutex::new(LruCache::new(shard_capacity)))
+                .collect(),
+            counters: Counters::default(),
         }
     }
 
-    pub fn get(&self, key: &CacheKey) -> Option<&FirewallPolicy> {
-        if self.active {
-            self.entries.get(key)
+    /// Policy used to build the keys of this cache.
+    #[must_use]
+    pub fn key_policy(&self) -> &CacheKeyPolicy {
+        &self.config.key_policy
+    }
+
+    /// Drops all the entries and applies new firewall defaults.
+    ///
+    /// The first time, the entries of a pending snapshot are restored
+    /// if it was taken under the same firewall defaults.
+    pub fn reset(&self, defaults: FirewallDefaults) {
+        self.active.store(defaults.cache, Ordering::Release);
+        *lock(&self.defaults) = Some(defaults);
+        for shard in &self.shards {
+            lock(shard).clear();
+        }
+
+        let Some(snapshot) = lock(&self.pending_snapshot).take() else {
+            return;
+        };
+        if !defaults.cache {
+            return;
+        }
+        let entries = snapshot.into_entries(&defaults);
+        log::info!("Restoring {} cache entries from snapshot", entries.len());
+        let now = Instant::now();
+        for (key, policy, ttl) in entries {
+            let expires_at = now + ttl;
+            lock(self.shard(&key)).put(key, CacheEntry { policy, expires_at });
+        }
+    }
+
+    /// Sets a snapshot to be restored by the next [`Cache::reset`].
+    pub(crate) fn restore_on_reset(&self, snapshot: CacheSnapshot) {
+        *lock(&self.pending_snapshot) = Some(snapshot);
+    }
+
+    /// Takes a snapshot of the `Deny` (and optionally `Allow`) entries;
+    /// nothing is returned before the firewall defaults are known.
+    pub(crate) fn snapshot(&self, include_allow: bool) -> Option<CacheSnapshot> {
+        let defaults = (*lock(&self.defaults))?;
+        let now = SystemTime::now();
+        let entries = self
+            .decisions()
+            .into_iter()
+            .filter(|decision| include_allow || decision.policy == FirewallPolicy::Deny)
+            .map(|decision| (decision.key, decision.policy, now + decision.expires_in));
+
+        Some(CacheSnapshot::new(&defaults, entries))
+    }
+
+    /// Returns the counters and current size of the cache.
+    #[must_use]
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            inserts: self.counters.inserts.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+            expirations: self.counters.expirations.load(Ordering::Relaxed),
+            ..CacheStats::default()
+        };
+
+        let now = Instant::now();
+        for shard in &self.shards {
+            for (_, entry) in lock(shard).iter() {
+                if entry.expires_at <= now {
+                    continue;
+                }
+                if entry.policy == FirewallPolicy::Deny {
+                    stats.deny_entries += 1;
+                } else {
+                    stats.allow_entries += 1;
+                }
+            }
+        }
+
+        stats
+    }
+
+    /// Returns all the decisions that haven't expired yet.
+    #[must_use]
+    pub fn decisions(&self) -> Vec<CachedDecision> {
+        let now = Instant::now();
+        let mut decisions = Vec::new();
+        for shard in &self.shards {
+            decisions.extend(
+                lock(shard)
+                    .iter()
+                    .filter(|(_, entry)| entry.expires_at > now)
+                    .map(|(key, entry)| CachedDecision {
+                        key: key.clone(),
+                        policy: entry.policy,
+                        expires_in: entry.expires_at - now,
+                    }),
+            );
+        }
+        decisions
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<FirewallPolicy> {
+        if !self.active.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let mut shard = lock(self.shard(key));
+        let Some(entry) = shard.get(key) else {
+            Counters::add(&self.counters.misses, 1);
+            return None;
+        };
+        if entry.expires_at <= Instant::now() {
+            shard.pop(key);
+            Counters::add(&self.counters.expirations, 1);
+            Counters::add(&self.counters.misses, 1);
+            return None;
+        }
+
+        Counters::add(&self.counters.hits, 1);
+        Some(entry.policy)
+    }
+
+    pub fn insert(&self, key: CacheKey, policy: FirewallPolicy) {
+        if !self.active.load(Ordering::Acquire) {
+            return;
+        }
+
+        let ttl = if policy == FirewallPolicy::Deny {
+            self.config.deny_ttl
         } else {
-            None
+            self.config.allow_ttl
+        };
+        if ttl.is_zero() {
+            return;
+        }
+
+        let expires_at = Instant::now() + ttl;
+        let shard = self.shard(&key);
+        // `push` also returns the previous entry when the key was already cached
+        let replaced = lock(shard).push(key.clone(), CacheEntry { policy, expires_at });
+        Counters::add(&self.counters.inserts, 1);
+        if replaced.is_some_and(|(replaced_key, _)| replaced_key != key) {
+            Counters::add(&self.counters.evictions, 1);
         }
     }
 
-    pub fn insert(&mut self, key: CacheKey, policy: FirewallPolicy) {
-        if self.active {
-            self.entries.insert(key, policy);
+    /// Drops the selected entries, returning how many were removed.
+    pub fn invalidate(&self, invalidation: &CacheInvalidation) -> usize {
+        if *invalidation == CacheInvalidation::All {
+            return self
+                .shards
+                .iter()
+                .map(|shard| {
+                    let mut shard = lock(shard);
+                    let len = shard.len();
+                    shard.clear();
+                    len
+                })
+                .sum();
         }
+
+        self.remove_where(|key, entry| invalidation.matches(key, entry.policy))
+    }
+
+    /// Removes the expired entries.
+    pub(crate) fn sweep(&self) {
+        let now = Instant::now();
+        let removed = self.remove_where(|_, entry| entry.expires_at <= now);
+        Counters::add(&self.counters.expirations, removed as u64);
+    }
+
+    fn remove_where(&self, predicate: impl Fn(&CacheKey, &CacheEntry) -> bool) -> usize {
+        let mut removed = 0;
+        for shard in &self.shards {
+            let mut shard = lock(shard);
+            let keys: Vec<CacheKey> = shard
+                .iter()
+                .filter(|(key, entry)| predicate(key, entry))
+                .map(|(key, _)| key.clone())
+                .collect();
+
+            removed += keys.len();
+            for key in keys {
+                shard.pop(&key);
+            }
+        }
+        removed
+    }
+
+    fn shard(&self, key: &CacheKey) -> &Mutex<Shard> {
+        let hash = self.hasher.hash_one(key);
+        #[allow(clippy::cast_possible_truncation)]
+        let index = hash as usize % self.shards.len();
+        &self.shards[index]
     }
 }
 
-/// Data structure used by clients to create a cache entry for each request.
-#[derive(PartialEq, Eq, Hash, Clone)]
-pub struct CacheKey {
-    pub original_url: String,
-    pub method: String,
-    pub query: BTreeMap<String, String>,
-    pub user_agent: String,
-    pub body: String,
-    pub source_ip: String,
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // the data is always left in a consistent state, so a poisoned lock can be reused
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Periodically removes the expired entries, until the cache is dropped.
+pub(crate) async fn sweep_periodically(cache: Weak<Cache>, interval: Duration) {
+    // `tokio::time::interval` panics on a zero period
+    if interval.is_zero() {
+        return;
+    }
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let Some(cache) = cache.upgrade() else {
+            return;
+        };
+        cache.sweep();
+    }
 }
@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Data structure used by clients to create a cache entry for each request.
+///
+/// Only the attributes selected by the [`CacheKeyPolicy`] in use are filled in.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default, Serialize, Deserialize)]
+pub struct CacheKey {
+    pub source_ip: Option<String>,
+    pub path: Option<String>,
+    pub method: Option<String>,
+    pub query: BTreeMap<String, String>,
+    pub headers: BTreeMap<String, String>,
+    /// SHA-256 digest of the inspected body prefix, hex-encoded.
+    pub body: Option<String>,
+}
+
+/// Request attributes a [`CacheKey`] can be made of.
+pub struct CacheKeySource<'a> {
+    pub source_ip: Option<&'a str>,
+    pub path: &'a str,
+    pub method: &'a str,
+    pub query: &'a HashMap<String, String>,
+    /// Headers, with lowercase names.
+    pub headers: &'a HashMap<String, String>,
+    /// Inspected prefix of the body, or `None` if the body isn't inspected.
+    pub body: Option<&'a [u8]>,
+}
+
+/// Selects the request attributes that cached firewall decisions depend on.
+///
+/// Requests that only differ in attributes left out of the key share the same cached decision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKeyPolicy {
+    /// Whether the key includes the source IP.
+    pub source_ip: bool,
+    /// Whether the key includes the path.
+    pub path: bool,
+    /// Whether the key includes the method.
+    pub method: bool,
+    /// Whether the key includes the query parameters.
+    pub query: bool,
+    /// Query parameters left out of the key (e.g. cache busters or tracking parameters).
+    pub ignored_query_params: BTreeSet<String>,
+    /// Lowercase names of the headers included in the key.
+    pub headers: BTreeSet<String>,
+    /// Whether the key includes the inspected body prefix.
+    ///
+    /// Requests with a body that isn't inspected are then never cached,
+    /// since their decision may depend on it.
+    pub body: bool,
+}
+
+impl Default for CacheKeyPolicy {
+    /// Source IP, path, method, query parameters, user agent and body.
+    fn default() -> Self {
+        Self {
+            source_ip: true,
+            path: true,
+            method: true,
+            query: true,
+            ignored_query_params: BTreeSet::new(),
+            headers: BTreeSet::from(["user-agent".to_string()]),
+            body: true,
+        }
+    }
+}
+
+impl CacheKeyPolicy {
+    /// Decisions only depend on the source IP.
+    #[must_use]
+    pub fn ip_only() -> Self {
+        Self {
+            source_ip: true,
+            ..Self::none()
+        }
+    }
+
+    /// Decisions depend on the source IP and the path.
+    #[must_use]
+    pub fn ip_and_path() -> Self {
+        Self {
+            source_ip: true,
+            path: true,
+            ..Self::none()
+        }
+    }
+
+    /// Decisions depend on the path and the method, regardless of the client.
+    #[must_use]
+    pub fn path_and_method() -> Self {
+        Self {
+            path: true,
+            method: true,
+            ..Self::none()
+        }
+    }
+
+    /// Leaves a query parameter out of the key.
+    #[must_use]
+    pub fn ignore_query_param(mut self, name: impl Into<String>) -> Self {
+        self.ignored_query_params.insert(name.into());
+        self
+    }
+
+    /// Includes a header in the key.
+    #[must_use]
+    pub fn include_header(mut self, name: impl Into<String>) -> Self {
+        self.headers.insert(name.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Builds the key of a request according to this policy,
+    /// or returns `None` if the request must not be cached because its body isn't inspected.
+    #[must_use]
+    pub fn key(&self, source: &CacheKeySource) -> Option<CacheKey> {
+        let body = if self.body {
+            match source.body {
+                Some(body) => Some(format!("{:x}", Sha256::digest(body))),
+                None if has_body(source.headers) => return None,
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        let query = if self.query {
+            source
+                .query
+                .iter()
+                .filter(|(name, _)| !self.ignored_query_params.contains(*name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
+
+        let headers = self
+            .headers
+            .iter()
+            .map(|name| {
+                let value = source.headers.get(name).cloned().unwrap_or_default();
+                (name.clone(), value)
+            })
+            .collect();
+
+        Some(CacheKey {
+            source_ip: self
+                .source_ip
+                .then(|| source.source_ip.unwrap_or_default().to_string()),
+            path: self.path.then(|| source.path.to_string()),
+            method: self.method.then(|| source.method.to_string()),
+            query,
+            headers,
+            body,
+        })
+    }
+
+    fn none() -> Self {
+        Self {
+            source_ip: false,
+            path: false,
+            method: false,
+            query: false,
+            ignored_query_params: BTreeSet::new(),
+            headers: BTreeSet::new(),
+            body: false,
+        }
+    }
+}
+
+/// Returns `true` if the headers announce a non-empty body.
+fn has_body(headers: &HashMap<String, String>) -> bool {
+    headers.contains_key("transfer-encoding")
+        || headers
+            .get("content-length")
+            .is_some_and(|len| len.trim() != "0")
+}
@@ -0,0 +1,156 @@
+use crate::cache::Cache;
+use crate::cache_key::CacheKey;
+use crate::storage::file_utils::write_atomically;
+use nullnet_libappguard::appguard_commands::{FirewallDefaults, FirewallPolicy};
+use nullnet_liberror::{Error, ErrorHandler, Location, location};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Weak;
+use std::time::{Duration, SystemTime};
+
+/// Settings of the on-disk snapshot of the firewall decisions cache.
+///
+/// The snapshot is restored at startup, unless the firewall defaults changed in the meantime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheSnapshotConfig {
+    /// Snapshot file (defaults to `decisions_cache.json` in the storage directory).
+    ///
+    /// With a custom secret store and no explicit storage directory, the path must be set,
+    /// otherwise the snapshot is disabled.
+    pub path: Option<PathBuf>,
+    /// Whether `Allow` decisions are persisted as well as `Deny` ones.
+    pub include_allow: bool,
+    /// How often the snapshot is written
+    /// (a zero duration disables the writes: an existing snapshot is only restored).
+    pub interval: Duration,
+}
+
+impl Default for CacheSnapshotConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            include_allow: false,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl CacheSnapshotConfig {
+    pub(crate) const FILE_NAME: &'static str = "decisions_cache.json";
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct SnapshotDefaults {
+    timeout: u32,
+    policy: i32,
+    cache: bool,
+}
+
+impl From<&FirewallDefaults> for SnapshotDefaults {
+    fn from(defaults: &FirewallDefaults) -> Self {
+        Self {
+            timeout: defaults.timeout,
+            policy: defaults.policy,
+            cache: defaults.cache,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotEntry {
+    key: CacheKey,
+    policy: i32,
+    expires_at: SystemTime,
+}
+
+/// Cached decisions, together with the firewall defaults they were taken under.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CacheSnapshot {
+    firewall_defaults: SnapshotDefaults,
+    entries: Vec<SnapshotEntry>,
+}
+
+impl CacheSnapshot {
+    pub(crate) fn new(
+        defaults: &FirewallDefaults,
+        entries: impl IntoIterator<Item = (CacheKey, FirewallPolicy, SystemTime)>,
+    ) -> Self {
+        Self {
+            firewall_defaults: defaults.into(),
+            entries: entries
+                .into_iter()
+                .map(|(key, policy, expires_at)| SnapshotEntry {
+                    key,
+                    policy: policy as i32,
+                    expires_at,
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the entries that haven't expired yet,
+    /// or nothing if the snapshot was taken under different firewall defaults.
+    pub(crate) fn into_entries(
+        self,
+        defaults: &FirewallDefaults,
+    ) -> Vec<(CacheKey, FirewallPolicy, Duration)> {
+        if self.firewall_defaults != SnapshotDefaults::from(defaults) {
+            log::info!("Firewall defaults changed, discarding the cache snapshot");
+            return Vec::new();
+        }
+
+        let now = SystemTime::now();
+        self.entries
+            .into_iter()
+            .filter_map(|entry| {
+                let policy = FirewallPolicy::try_from(entry.policy).ok()?;
+                let ttl = entry.expires_at.duration_since(now).ok()?;
+                Some((entry.key, policy, ttl))
+            })
+            .collect()
+    }
+
+    /// Reads a snapshot; a missing or unreadable file yields no snapshot.
+    pub(crate) async fn load(path: &Path) -> Option<Self> {
+        let content = tokio::fs::read(path).await.ok()?;
+        serde_json::from_slice(&content)
+            .inspect_err(|err| log::warn!("Ignoring unreadable cache snapshot: {err}"))
+            .ok()
+    }
+
+    async fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .handle_err(location!())?;
+        }
+        let content = serde_json::to_vec(self).handle_err(location!())?;
+        write_atomically(path, &content).await
+    }
+}
+
+/// Periodically writes the snapshot of the cache, until the cache is dropped.
+pub(crate) async fn save_periodically(
+    cache: Weak<Cache>,
+    path: PathBuf,
+    config: CacheSnapshotConfig,
+) {
+    // `tokio::time::interval` panics on a zero period
+    if config.interval.is_zero() {
+        return;
+    }
+    let mut ticker = tokio::time::interval(config.interval);
+    // the first tick completes immediately, before anything could be cached
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let Some(cache) = cache.upgrade() else {
+            return;
+        };
+        if let Some(snapshot) = cache.snapshot(config.include_allow)
+            && let Err(err) = snapshot.save(&path).await
+        {
+            log::warn!("Failed to save the cache snapshot: {}", err.to_str());
+        }
+    }
+}
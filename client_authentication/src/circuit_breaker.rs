@@ -0,0 +1,118 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Settings of the circuit breaker guarding the calls to the `AppGuard` server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failed calls after which the circuit opens.
+    pub failure_threshold: u32,
+    /// How long the server isn't called once the circuit is open.
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Stops calling the `AppGuard` server for a cooldown period after repeated failures.
+///
+/// Once the cooldown has elapsed, a single call is let through to probe the server:
+/// if it succeeds the circuit closes, otherwise a new cooldown starts.
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    failures: AtomicU32,
+    opened_at: Mutex<Option<Instant>>,
+}
+
+impl CircuitBreaker {
+    #[must_use]
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            failures: AtomicU32::new(0),
+            opened_at: Mutex::new(None),
+        }
+    }
+
+    /// Returns `true` if the server can be called.
+    pub fn allows_request(&self) -> bool {
+        let mut opened_at = self
+            .opened_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match *opened_at {
+            None => true,
+            Some(at) if at.elapsed() >= self.config.cooldown => {
+                // let this call probe the server, while the others keep waiting
+                *opened_at = Some(Instant::now());
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    /// Returns `true` while the server is not being called.
+    #[must_use]
+    pub fn is_open(&self) -> bool {
+        self.opened_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+    }
+
+    pub fn record_success(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+        let mut opened_at = self
+            .opened_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if opened_at.take().is_some() {
+            log::info!("AppGuard server reachable again, closing the circuit breaker");
+        }
+    }
+
+    pub fn record_failure(&self) {
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.config.failure_threshold {
+            let mut opened_at = self
+                .opened_at
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if opened_at.is_none() {
+                log::warn!(
+                    "{failures} consecutive AppGuard server failures, not calling it for {:?}",
+                    self.config.cooldown
+                );
+            }
+            *opened_at = Some(Instant::now());
+        }
+    }
+
+    /// Runs a call to the server through the circuit breaker.
+    ///
+    /// Returns `None` if the call failed or the circuit is open.
+    pub async fn call<T, E: Display>(&self, call: impl Future<Output = Result<T, E>>) -> Option<T> {
+        if !self.allows_request() {
+            return None;
+        }
+
+        match call.await {
+            Ok(value) => {
+                self.record_success();
+                Some(value)
+            }
+            Err(err) => {
+                log::warn!("AppGuard server call failed: {err}");
+                self.record_failure();
+                None
+            }
+        }
+    }
+}
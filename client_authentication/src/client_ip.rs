@@ -0,0 +1,298 @@
+use ipnet::IpNet;
+use nullnet_liberror::{Error, ErrorHandler, Location, location};
+use std::net::{IpAddr, SocketAddr};
+
+/// Client address supplied by the PROXY protocol, for connections accepted through a load balancer using it.
+///
+/// Listeners decoding the PROXY protocol header attach it to the requests of the connection,
+/// so that it's taken into account by the [`ClientIpResolver`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyProtocolAddress(pub SocketAddr);
+
+/// Request attributes the client IP is resolved from.
+#[derive(Default)]
+pub struct ClientIpSource<'a> {
+    /// Address of the peer of the connection.
+    pub peer: Option<IpAddr>,
+    /// Address supplied by the PROXY protocol, if any.
+    pub proxy_protocol: Option<IpAddr>,
+    /// Values of the `Forwarded` headers, in order.
+    pub forwarded: Vec<&'a str>,
+    /// Values of the `X-Forwarded-For` headers, in order.
+    pub x_forwarded_for: Vec<&'a str>,
+    /// Value of the `X-Real-IP` header.
+    pub x_real_ip: Option<&'a str>,
+}
+
+/// Forwarding header the trusted proxies set to the client IP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardedHeader {
+    /// `Forwarded` (RFC 7239), reading the `for` parameter of each element.
+    Forwarded,
+    /// `X-Forwarded-For` (e.g. nginx with `$proxy_add_x_forwarded_for`, AWS ALB).
+    XForwardedFor,
+    /// `X-Real-IP`, holding a single address.
+    XRealIp,
+}
+
+/// Resolves the IP of the clients behind trusted reverse proxies.
+///
+/// Forwarding information (PROXY protocol, then the configured [`ForwardedHeader`])
+/// is only considered when the connection comes from a trusted proxy, and the forwarding chain
+/// is walked from the closest hop until an address that isn't a trusted proxy is found.
+/// Other forwarding headers are ignored, since clients can send them through the proxies.
+/// Without trusted proxies (the default), the client IP is the peer address.
+#[derive(Debug, Clone, Default)]
+pub struct ClientIpResolver {
+    trusted_proxies: Vec<IpNet>,
+    forwarded_header: Option<ForwardedHeader>,
+}
+
+impl ClientIpResolver {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts the proxies in a CIDR block (e.g. `10.0.0.0/8`); a single IP is also accepted.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn trust_proxy(mut self, cidr: &str) -> Result<Self, Error> {
+        self.trusted_proxies.push(parse_cidr(cidr)?);
+        Ok(self)
+    }
+
+    /// Reads the client IP from the header set by the trusted proxies
+    /// (defaults to none, i.e. only the PROXY protocol is followed).
+    #[must_use]
+    pub fn forwarded_header(mut self, header: ForwardedHeader) -> Self {
+        self.forwarded_header = Some(header);
+        self
+    }
+
+    /// Returns `true` if the address belongs to a trusted proxy.
+    #[must_use]
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Returns the IP of the client that originated the request.
+    #[must_use]
+    pub fn resolve(&self, source: &ClientIpSource) -> Option<IpAddr> {
+        let mut addr = source.peer;
+        if let Some(proxied) = source.proxy_protocol
+            && addr.is_none_or(|peer| self.is_trusted(peer))
+        {
+            addr = Some(proxied);
+        }
+        let addr = addr?;
+        if !self.is_trusted(addr) {
+            return Some(addr);
+        }
+
+        let chain: Vec<Option<IpAddr>> = match self.forwarded_header {
+            Some(ForwardedHeader::Forwarded) => source
+                .forwarded
+                .iter()
+                .flat_map(|value| value.split(','))
+                .map(forwarded_for)
+                .collect(),
+            Some(ForwardedHeader::XForwardedFor) => source
+                .x_forwarded_for
+                .iter()
+                .flat_map(|value| value.split(','))
+                .map(parse_ip)
+                .collect(),
+            Some(ForwardedHeader::XRealIp) => source.x_real_ip.map(parse_ip).into_iter().collect(),
+            None => Vec::new(),
+        };
+
+        let mut client = addr;
+        for hop in chain.into_iter().rev() {
+            // obfuscated or malformed hops can't be followed
+            let Some(hop) = hop else {
+                break;
+            };
+            client = hop;
+            if !self.is_trusted(hop) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+/// Parses a CIDR block, or a single IP.
+pub(crate) fn parse_cidr(cidr: &str) -> Result<IpNet, Error> {
+    match cidr.parse::<IpNet>() {
+        Ok(net) => Ok(net),
+        Err(_) => Ok(IpNet::from(cidr.parse::<IpAddr>().handle_err(location!())?)),
+    }
+}
+
+/// Extracts the `for` parameter of a `Forwarded` header element.
+fn forwarded_for(element: &str) -> Option<IpAddr> {
+    element.split(';').find_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("for")
+            .then(|| parse_ip(value))?
+    })
+}
+
+/// Parses an IP, possibly quoted, bracketed or followed by a port (`"[2001:db8::1]:4711"`, `192.0.2.1:80`).
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(socket) = value.parse::<SocketAddr>() {
+        return Some(socket.ip());
+    }
+    let bracketed = value.strip_prefix('[')?.split(']').next()?;
+    bracketed.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROXY: &str = "10.0.0.1";
+    const CLIENT: &str = "203.0.113.7";
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn resolver(header: ForwardedHeader) -> ClientIpResolver {
+        ClientIpResolver::new()
+            .trust_proxy("10.0.0.0/8")
+            .unwrap()
+            .forwarded_header(header)
+    }
+
+    fn from_proxy<'a>() -> ClientIpSource<'a> {
+        ClientIpSource {
+            peer: Some(ip(PROXY)),
+            ..ClientIpSource::default()
+        }
+    }
+
+    #[test]
+    fn headers_are_ignored_from_untrusted_peers() {
+        let source = ClientIpSource {
+            peer: Some(ip(CLIENT)),
+            x_forwarded_for: vec!["198.51.100.1"],
+            ..ClientIpSource::default()
+        };
+        let resolved = resolver(ForwardedHeader::XForwardedFor).resolve(&source);
+        assert_eq!(resolved, Some(ip(CLIENT)));
+    }
+
+    #[test]
+    fn headers_are_ignored_by_default() {
+        let resolver = ClientIpResolver::new().trust_proxy(PROXY).unwrap();
+        let source = ClientIpSource {
+            forwarded: vec!["for=198.51.100.1"],
+            x_forwarded_for: vec!["198.51.100.1"],
+            x_real_ip: Some("198.51.100.1"),
+            ..from_proxy()
+        };
+        assert_eq!(resolver.resolve(&source), Some(ip(PROXY)));
+    }
+
+    #[test]
+    fn only_the_configured_header_is_followed() {
+        // e.g. nginx setting `X-Real-IP`, while the client sends its own `X-Forwarded-For`
+        let source = ClientIpSource {
+            x_forwarded_for: vec!["198.51.100.1"],
+            x_real_ip: Some(CLIENT),
+            ..from_proxy()
+        };
+        let resolved = resolver(ForwardedHeader::XRealIp).resolve(&source);
+        assert_eq!(resolved, Some(ip(CLIENT)));
+    }
+
+    #[test]
+    fn spoofed_leftmost_hops_are_ignored() {
+        let source = ClientIpSource {
+            x_forwarded_for: vec!["198.51.100.1, 192.0.2.1", "203.0.113.7, 10.0.0.2"],
+            ..from_proxy()
+        };
+        let resolved = resolver(ForwardedHeader::XForwardedFor).resolve(&source);
+        assert_eq!(resolved, Some(ip(CLIENT)));
+    }
+
+    #[test]
+    fn forwarded_elements_are_parsed() {
+        let source = ClientIpSource {
+            forwarded: vec![
+                "for=198.51.100.1;proto=https",
+                r#"for="[2001:db8::1]:4711";by=10.0.0.2, For=10.0.0.2"#,
+            ],
+            ..from_proxy()
+        };
+        let resolved = resolver(ForwardedHeader::Forwarded).resolve(&source);
+        assert_eq!(resolved, Some(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn malformed_or_obfuscated_hops_stop_the_walk() {
+        let source = ClientIpSource {
+            forwarded: vec!["for=198.51.100.1, for=_hidden, for=10.0.0.2"],
+            ..from_proxy()
+        };
+        let resolved = resolver(ForwardedHeader::Forwarded).resolve(&source);
+        assert_eq!(resolved, Some(ip("10.0.0.2")));
+
+        let source = ClientIpSource {
+            x_forwarded_for: vec!["198.51.100.1, not-an-ip"],
+            ..from_proxy()
+        };
+        let resolved = resolver(ForwardedHeader::XForwardedFor).resolve(&source);
+        assert_eq!(resolved, Some(ip(PROXY)));
+    }
+
+    #[test]
+    fn addresses_with_ports_are_parsed() {
+        let source = ClientIpSource {
+            x_forwarded_for: vec!["203.0.113.7:51234"],
+            ..from_proxy()
+        };
+        let resolved = resolver(ForwardedHeader::XForwardedFor).resolve(&source);
+        assert_eq!(resolved, Some(ip(CLIENT)));
+    }
+
+    #[test]
+    fn proxy_protocol_takes_precedence_over_headers() {
+        let source = ClientIpSource {
+            proxy_protocol: Some(ip(CLIENT)),
+            x_forwarded_for: vec!["198.51.100.1"],
+            ..from_proxy()
+        };
+        let resolved = resolver(ForwardedHeader::XForwardedFor).resolve(&source);
+        assert_eq!(resolved, Some(ip(CLIENT)));
+    }
+
+    #[test]
+    fn proxy_protocol_is_ignored_from_untrusted_peers() {
+        let source = ClientIpSource {
+            peer: Some(ip("192.0.2.1")),
+            proxy_protocol: Some(ip(CLIENT)),
+            ..ClientIpSource::default()
+        };
+        let resolved = resolver(ForwardedHeader::XForwardedFor).resolve(&source);
+        assert_eq!(resolved, Some(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn proxy_protocol_from_a_trusted_load_balancer_is_followed_by_headers() {
+        // the load balancer itself sits behind another trusted proxy
+        let source = ClientIpSource {
+            proxy_protocol: Some(ip("10.0.0.3")),
+            x_forwarded_for: vec![CLIENT],
+            ..from_proxy()
+        };
+        let resolved = resolver(ForwardedHeader::XForwardedFor).resolve(&source);
+        assert_eq!(resolved, Some(ip(CLIENT)));
+    }
+}
@@ -0,0 +1,23 @@
+/// Lifecycle of an `AppGuard` client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientState {
+    /// The client has no credentials and hasn't asked to be authorized yet.
+    Unauthorized,
+    /// The client has sent an authorization request and is waiting for the server's verdict.
+    AwaitingApproval,
+    /// The client is authorized and authenticated on the control channel.
+    Authorized,
+    /// The device has been deauthorized by the server;
+    /// the client stays idle until a new installation code is provided.
+    Deauthorized,
+    /// The authorization request has been rejected by the server;
+    /// the client stays idle until a new installation code is provided.
+    Rejected,
+}
+
+impl ClientState {
+    /// Returns `true` if the client is waiting for a new installation code.
+    pub(crate) fn is_idle(self) -> bool {
+        matches!(self, ClientState::Deauthorized | ClientState::Rejected)
+    }
+}
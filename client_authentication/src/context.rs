@@ -1,68 +1,329 @@
-use crate::cache::Cache;
-use crate::control_channel::start_control_stream;
-use crate::storage::{Secret, Storage};
-use crate::token_provider::TokenProvider;
+use crate::bypass::{BypassAction, BypassRequest, BypassRule};
+use crate::cache::{
+    Cache, CacheConfig, CacheInvalidation, CacheStats, CachedDecision, sweep_periodically,
+};
+use crate::cache_snapshot::{CacheSnapshot, save_periodically};
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+use crate::client_ip::ClientIpResolver;
+use crate::client_state::ClientState;
+use crate::context_builder::ContextBuilder;
+use crate::control_channel::command::ExecutableCommand;
+use crate::control_channel::commands::{InvalidateCacheCommand, SetMonitorOnlyCommand};
+use crate::control_channel::{ControlChannelSettings, start_control_stream};
+use crate::fallback_policy::FallbackPolicy;
+use crate::monitor_mode::MonitorMode;
+use crate::response_inspection::ResponseInspection;
+use crate::response_reporter::{ResponseReporter, ResponseReportingConfig};
+use crate::response_template::ResponseTemplate;
+use crate::startup_mode::StartupMode;
+use crate::storage::{Secret, SecretStore};
+use crate::token_provider::{RetrievalStrategy, TokenProvider};
 use nullnet_libappguard::AppGuardGrpcInterface;
-use nullnet_libappguard::appguard_commands::FirewallDefaults;
+use nullnet_libappguard::appguard_commands::{FirewallDefaults, FirewallPolicy};
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, watch};
+use tokio::time::Instant;
+
+/// Settings of a [`Context`] that don't concern the connection to the server.
+pub(crate) struct ContextSettings {
+    pub(crate) fallback_policy: FallbackPolicy,
+    pub(crate) failure_policy: FallbackPolicy,
+    pub(crate) deny_response: ResponseTemplate,
+    pub(crate) failure_response: ResponseTemplate,
+    pub(crate) circuit_breaker: CircuitBreakerConfig,
+    pub(crate) request_body_limit: Option<usize>,
+    pub(crate) response_inspection: Option<ResponseInspection>,
+    pub(crate) response_reporting: Option<ResponseReportingConfig>,
+    pub(crate) monitor_only: bool,
+    pub(crate) bypass_rules: Vec<BypassRule>,
+    pub(crate) client_ip_resolver: ClientIpResolver,
+    pub(crate) startup_mode: StartupMode,
+    pub(crate) cache_config: CacheConfig,
+}
 
 #[derive(Clone)]
 pub struct Context {
     pub token_provider: TokenProvider,
     pub server: AppGuardGrpcInterface,
     pub firewall_defaults: Arc<Mutex<FirewallDefaults>>,
-    pub cache: Arc<Mutex<Cache>>,
+    pub cache: Arc<Cache>,
+    /// Policy applied by the middlewares while the client is not ready.
+    pub fallback_policy: FallbackPolicy,
+    /// Policy applied by the middlewares when the server can't be consulted for a request.
+    pub failure_policy: FallbackPolicy,
+    /// Response to the requests denied by the firewall or by the fallback policy.
+    pub deny_response: ResponseTemplate,
+    /// Response to the requests rejected because the server couldn't be consulted.
+    pub failure_response: ResponseTemplate,
+    pub circuit_breaker: Arc<CircuitBreaker>,
+    /// Maximum number of request body bytes forwarded to the server for inspection
+    /// (`None` if bodies are not inspected).
+    pub request_body_limit: Option<usize>,
+    /// Inspection of the response bodies (`None` if bodies are not inspected).
+    pub response_inspection: Option<ResponseInspection>,
+    /// Background reporting of the responses (`None` if they're reported before being returned).
+    pub response_reporter: Option<Arc<ResponseReporter>>,
+    /// Monitor-only mode, in which the verdicts are logged and counted but not enforced.
+    pub monitor_mode: Arc<MonitorMode>,
+    bypass_rules: Arc<[BypassRule]>,
+    /// Resolution of the client IPs behind trusted proxies.
+    pub client_ip_resolver: Arc<ClientIpResolver>,
+    pub(crate) store: Arc<dyn SecretStore>,
+    state: Arc<watch::Sender<ClientState>>,
+    initialized: Arc<AtomicBool>,
 }
 
 impl Context {
+    /// Creates a new context, reading the configuration from the environment.
     #[allow(clippy::missing_errors_doc)]
     pub async fn new(r#type: String) -> Result<Self, Error> {
-        let host = std::env::var("CONTROL_SERVICE_ADDR").handle_err(location!())?;
-        let port_str = std::env::var("CONTROL_SERVICE_PORT").handle_err(location!())?;
-        let port = port_str.parse::<u16>().handle_err(location!())?;
+        Self::builder().client_type(r#type).build().await
+    }
 
-        let mut server = AppGuardGrpcInterface::new(&host, port, false)
-            .await
-            .handle_err(location!())?;
+    /// Returns a builder to configure the context programmatically.
+    #[must_use]
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder::new()
+    }
 
-        Storage::init().await?;
+    pub(crate) async fn start(
+        server: AppGuardGrpcInterface,
+        store: Arc<dyn SecretStore>,
+        control_channel: ControlChannelSettings,
+        settings: ContextSettings,
+    ) -> Result<Self, Error> {
+        let ContextSettings {
+            fallback_policy,
+            failure_policy,
+            deny_response,
+            failure_response,
+            circuit_breaker,
+            request_body_limit,
+            response_inspection,
+            response_reporting,
+            monitor_only,
+            bypass_rules,
+            client_ip_resolver,
+            startup_mode,
+            cache_config,
+        } = settings;
+        let sweep_interval = cache_config.sweep_interval;
+        let snapshot = cache_config.snapshot.clone();
+        let firewall_defaults = Arc::new(Mutex::new(FirewallDefaults::default()));
+        let cache = Arc::new(Cache::new(cache_config));
+        let circuit_breaker = Arc::new(CircuitBreaker::new(circuit_breaker));
+        let response_reporter = response_reporting.map(|config| {
+            Arc::new(ResponseReporter::start(
+                config,
+                server.clone(),
+                firewall_defaults.clone(),
+                cache.clone(),
+                circuit_breaker.clone(),
+            ))
+        });
+        let ctx = Self {
+            token_provider: TokenProvider::new(),
+            server,
+            store,
+            firewall_defaults,
+            cache,
+            fallback_policy,
+            failure_policy,
+            deny_response,
+            failure_response,
+            circuit_breaker,
+            request_body_limit,
+            response_inspection,
+            response_reporter,
+            monitor_mode: Arc::new(MonitorMode::new(monitor_only)),
+            bypass_rules: bypass_rules.into(),
+            client_ip_resolver: Arc::new(client_ip_resolver),
+            state: Arc::new(watch::Sender::new(ClientState::Unauthorized)),
+            initialized: Arc::new(AtomicBool::new(false)),
+        };
 
-        let mut installation_code_res = std::env::var("INSTALLATION_CODE").handle_err(location!());
-        if installation_code_res.is_err() {
-            installation_code_res = Storage::get_value(Secret::InstallationCode)
-                .await
-                .ok_or("Installation code not set")
-                .handle_err(location!());
+        // background tasks, stopped if the client fails to start
+        let mut tasks = vec![tokio::spawn(sweep_periodically(
+            Arc::downgrade(&ctx.cache),
+            sweep_interval,
+        ))];
+        if let Some(snapshot) = snapshot
+            && let Some(path) = snapshot.path.clone()
+        {
+            if let Some(restored) = CacheSnapshot::load(&path).await {
+                ctx.cache.restore_on_reset(restored);
+            }
+            tasks.push(tokio::spawn(save_periodically(
+                Arc::downgrade(&ctx.cache),
+                path,
+                snapshot,
+            )));
         }
-        let installation_code = installation_code_res?;
-        Storage::set_value(Secret::InstallationCode, &installation_code).await?;
+        let mut retry_backoff = control_channel.backoff.clone();
+        tasks.push(start_control_stream(ctx.clone(), control_channel).await);
 
-        let token_provider = TokenProvider::new();
+        match startup_mode {
+            StartupMode::Blocking(timeout) => {
+                let deadline = timeout.map(|timeout| Instant::now() + timeout);
+                if let Err(err) = ctx.initialize(deadline).await {
+                    for task in tasks {
+                        task.abort();
+                    }
+                    return Err(err);
+                }
+            }
+            StartupMode::Background => {
+                let ctx = ctx.clone();
+                tokio::spawn(async move {
+                    while let Err(err) = ctx.initialize(None).await {
+                        // the defaults may have been pushed by the server in the meantime
+                        if ctx.initialized.load(Ordering::Acquire) {
+                            break;
+                        }
+                        let delay = retry_backoff.next_delay();
+                        log::error!(
+                            "Context initialization failed: {} (retrying in {} ms)",
+                            err.to_str(),
+                            delay.as_millis()
+                        );
+                        tokio::time::sleep(delay).await;
+                    }
+                });
+            }
+        }
 
-        let ctx = Self {
-            token_provider: token_provider.clone(),
-            server: server.clone(),
-            firewall_defaults: Arc::new(Mutex::new(FirewallDefaults::default())),
-            cache: Arc::new(Mutex::new(Cache::new(FirewallDefaults::default()))),
+        Ok(ctx)
+    }
+
+    /// Waits for the first token and retrieves the firewall defaults, both before the deadline.
+    async fn initialize(&self, deadline: Option<Instant>) -> Result<(), Error> {
+        let timeout = deadline.map_or(Duration::MAX, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        });
+        let token = self
+            .token_provider
+            .obtain(RetrievalStrategy::Await(timeout))
+            .await
+            .ok_or("Timed out waiting for the client to be authorized")
+            .handle_err(location!())?;
+
+        let mut server = self.server.clone();
+        let request = server.firewall_defaults_request(token);
+        let response = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, request)
+                .await
+                .handle_err(location!())?,
+            None => request.await,
         };
+        let firewall_defaults = response.handle_err(location!())?;
+        *self.firewall_defaults.lock().await = firewall_defaults;
+        self.cache.reset(firewall_defaults);
+        self.set_initialized();
 
-        start_control_stream(ctx.clone(), installation_code, r#type).await;
+        Ok(())
+    }
 
-        let mut token = token_provider.get().await.unwrap_or_default();
-        while token.is_empty() {
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-            token = token_provider.get().await.unwrap_or_default();
-        }
+    /// Returns `true` once the client is authorized, holds a valid token and has received its firewall defaults.
+    ///
+    /// While this is `false`, the middlewares apply the fallback policy.
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        self.initialized.load(Ordering::Acquire)
+            && self.state() == ClientState::Authorized
+            && self.token_provider.has_token()
+    }
+
+    /// Returns the current lifecycle state of the client.
+    #[must_use]
+    pub fn state(&self) -> ClientState {
+        *self.state.borrow()
+    }
 
-        let firewall_defaults = server
-            .firewall_defaults_request(token)
+    /// Returns a receiver notified every time the lifecycle state of the client changes.
+    #[must_use]
+    pub fn subscribe_state(&self) -> watch::Receiver<ClientState> {
+        self.state.subscribe()
+    }
+
+    /// Provides a new installation code, so that a deauthorized or rejected client can re-enter the authorization flow.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn reauthorize(&self, installation_code: impl Into<String>) -> Result<(), Error> {
+        self.store
+            .set_value(Secret::InstallationCode, &installation_code.into())
+            .await?;
+        self.state.send_if_modified(|state| {
+            let idle = state.is_idle();
+            if idle {
+                *state = ClientState::Unauthorized;
+            }
+            idle
+        });
+        Ok(())
+    }
+
+    /// Resolves the policy to apply when the server can't be consulted for a request.
+    pub async fn resolve_failure_policy(&self) -> FirewallPolicy {
+        let fw_defaults = *self.firewall_defaults.lock().await;
+        self.failure_policy.resolve(&fw_defaults)
+    }
+
+    /// Returns the action of the first bypass rule matching the request, if any.
+    #[must_use]
+    pub fn bypass_action(&self, request: &BypassRequest) -> Option<BypassAction> {
+        self.bypass_rules
+            .iter()
+            .find(|rule| rule.matches(request))
+            .map(BypassRule::action)
+    }
+
+    /// Returns the counters and current size of the decisions cache.
+    #[must_use]
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Returns the `Deny` decisions currently cached, e.g. to find out why a client is blocked.
+    #[must_use]
+    pub fn cached_denies(&self) -> Vec<CachedDecision> {
+        self.cache
+            .decisions()
+            .into_iter()
+            .filter(|decision| decision.policy == FirewallPolicy::Deny)
+            .collect()
+    }
+
+    /// Drops the selected cached decisions, so that the matching requests are checked again by the server.
+    ///
+    /// This applies the same invalidation as the corresponding control channel command,
+    /// e.g. to forward a block decided by another system.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn invalidate_cache(&self, invalidation: CacheInvalidation) -> Result<(), Error> {
+        InvalidateCacheCommand::new(self.clone(), invalidation)
+            .execute()
             .await
-            .handle_err(location!())?;
-        *ctx.firewall_defaults.lock().await = firewall_defaults;
-        *ctx.cache.lock().await = Cache::new(firewall_defaults);
+    }
 
-        Ok(ctx)
+    /// Enables or disables monitor-only mode at runtime.
+    ///
+    /// This applies the same switch as the corresponding control channel command,
+    /// e.g. to validate new rules against real traffic before enforcing them.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn set_monitor_only(&self, enabled: bool) -> Result<(), Error> {
+        SetMonitorOnlyCommand::new(self.clone(), enabled)
+            .execute()
+            .await
+    }
+
+    /// Marks the context as initialized, once the firewall defaults have been received.
+    pub(crate) fn set_initialized(&self) {
+        self.initialized.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn set_state(&self, state: ClientState) {
+        log::info!("Client state: {state:?}");
+        self.state.send_replace(state);
     }
 }
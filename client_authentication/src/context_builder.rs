@@ -0,0 +1,428 @@
+use crate::bypass::BypassRule;
+use crate::cache::CacheConfig;
+use crate::cache_snapshot::CacheSnapshotConfig;
+use crate::circuit_breaker::CircuitBreakerConfig;
+use crate::client_ip::ClientIpResolver;
+use crate::context::{Context, ContextSettings};
+use crate::control_channel::{Backoff, ControlChannelSettings};
+use crate::device_identity::DeviceIdentity;
+use crate::fallback_policy::FallbackPolicy;
+use crate::response_inspection::{ResponseBodyInspector, ResponseInspection};
+use crate::response_reporter::ResponseReportingConfig;
+use crate::response_template::ResponseTemplate;
+use crate::startup_mode::StartupMode;
+use crate::storage::{JsonFileStore, Secret, SecretStore, StorageKey};
+use nullnet_libappguard::AppGuardGrpcInterface;
+use nullnet_liberror::{Error, ErrorHandler, Location, location};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Builder used to configure a [`Context`] programmatically.
+///
+/// Every setting that is not explicitly provided falls back to the corresponding environment variable
+/// (`CONTROL_SERVICE_ADDR`, `CONTROL_SERVICE_PORT`, `INSTALLATION_CODE`, ...) or to a sensible default.
+///
+/// The builder can produce any type that can be created from a [`Context`],
+/// which is how the framework-specific middlewares expose their own `builder()`.
+pub struct ContextBuilder<T = Context> {
+    address: Option<String>,
+    port: Option<u16>,
+    tls: bool,
+    installation_code: Option<String>,
+    secret_store: Option<Arc<dyn SecretStore>>,
+    storage_dir: Option<PathBuf>,
+    storage_key: Option<StorageKey>,
+    client_type: String,
+    device_identities: Vec<DeviceIdentity>,
+    token_refresh_margin: Duration,
+    connect_timeout: Option<Duration>,
+    reconnect_initial_delay: Duration,
+    reconnect_max_delay: Duration,
+    fallback_policy: FallbackPolicy,
+    failure_policy: FallbackPolicy,
+    deny_response: ResponseTemplate,
+    failure_response: ResponseTemplate,
+    circuit_breaker: CircuitBreakerConfig,
+    request_body_limit: Option<usize>,
+    response_inspection: Option<ResponseInspection>,
+    response_inspection_timeout: Duration,
+    response_reporting: Option<ResponseReportingConfig>,
+    monitor_only: bool,
+    bypass_rules: Vec<BypassRule>,
+    client_ip_resolver: ClientIpResolver,
+    startup_mode: StartupMode,
+    cache_config: CacheConfig,
+    target: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for ContextBuilder<T> {
+    fn default() -> Self {
+        Self {
+            address: None,
+            port: None,
+            tls: false,
+            installation_code: None,
+            secret_store: None,
+            storage_dir: None,
+            storage_key: None,
+            client_type: String::from("Rust"),
+            device_identities: DeviceIdentity::default_chain(),
+            token_refresh_margin: Duration::from_secs(60),
+            connect_timeout: None,
+            reconnect_initial_delay: Duration::from_secs(1),
+            reconnect_max_delay: Duration::from_secs(60),
+            fallback_policy: FallbackPolicy::default(),
+            failure_policy: FallbackPolicy::default(),
+            deny_response: ResponseTemplate::text(401, "Unauthorized"),
+            failure_response: ResponseTemplate::text(503, "Service Unavailable"),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            request_body_limit: None,
+            response_inspection: None,
+            response_inspection_timeout: Duration::from_secs(1),
+            response_reporting: None,
+            monitor_only: false,
+            bypass_rules: Vec::new(),
+            client_ip_resolver: ClientIpResolver::default(),
+            startup_mode: StartupMode::default(),
+            cache_config: CacheConfig::default(),
+            target: PhantomData,
+        }
+    }
+}
+
+impl<T> ContextBuilder<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `AppGuard` server's address (defaults to `CONTROL_SERVICE_ADDR`).
+    #[must_use]
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    /// `AppGuard` server's port (defaults to `CONTROL_SERVICE_PORT`).
+    #[must_use]
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Whether to connect to the `AppGuard` server over TLS (defaults to `false`).
+    #[must_use]
+    pub fn tls(mut self, tls: bool) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Installation code for this client (defaults to `INSTALLATION_CODE`, then to the stored one).
+    #[must_use]
+    pub fn installation_code(mut self, installation_code: impl Into<String>) -> Self {
+        self.installation_code = Some(installation_code.into());
+        self
+    }
+
+    /// Backend used to persist the client credentials
+    /// (defaults to a [`JsonFileStore`] located in [`storage_dir`](Self::storage_dir)).
+    #[must_use]
+    pub fn secret_store(mut self, secret_store: impl SecretStore + 'static) -> Self {
+        self.secret_store = Some(Arc::new(secret_store));
+        self
+    }
+
+    /// Directory where the client credentials are stored by the default store
+    /// (defaults to `<config dir>/appguard`).
+    #[must_use]
+    pub fn storage_dir(mut self, storage_dir: impl Into<PathBuf>) -> Self {
+        self.storage_dir = Some(storage_dir.into());
+        self
+    }
+
+    /// Key used by the default store to encrypt the credentials
+    /// (defaults to `APPGUARD_STORAGE_PASSPHRASE` or `APPGUARD_STORAGE_KEY_FILE`, if set).
+    #[must_use]
+    pub fn storage_key(mut self, storage_key: StorageKey) -> Self {
+        self.storage_key = Some(storage_key);
+        self
+    }
+
+    /// Label identifying the kind of client in the authorization request.
+    #[must_use]
+    pub fn client_type(mut self, client_type: impl Into<String>) -> Self {
+        self.client_type = client_type.into();
+        self
+    }
+
+    /// Sources of the device UUID, tried in order until one succeeds
+    /// (defaults to [`DeviceIdentity::default_chain`]).
+    #[must_use]
+    pub fn device_identity(mut self, chain: impl IntoIterator<Item = DeviceIdentity>) -> Self {
+        self.device_identities = chain.into_iter().collect();
+        self
+    }
+
+    /// Maximum time to wait for the connection to the `AppGuard` server (no limit by default).
+    #[must_use]
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Initial and maximum delay between control channel reconnection attempts
+    /// (defaults to 1 and 60 seconds).
+    #[must_use]
+    pub fn reconnect_delay(mut self, initial: Duration, max: Duration) -> Self {
+        self.reconnect_initial_delay = initial;
+        self.reconnect_max_delay = max;
+        self
+    }
+
+    /// How long before the token expiration the client re-authenticates,
+    /// if the server hasn't pushed a new token yet (defaults to 60 seconds).
+    #[must_use]
+    pub fn token_refresh_margin(mut self, margin: Duration) -> Self {
+        self.token_refresh_margin = margin;
+        self
+    }
+
+    /// Policy applied to requests while the client is not ready
+    /// (e.g. while awaiting authorization, or after the device has been deauthorized).
+    #[must_use]
+    pub fn fallback_policy(mut self, policy: FallbackPolicy) -> Self {
+        self.fallback_policy = policy;
+        self
+    }
+
+    /// Policy applied to requests when the server can't be consulted
+    /// (e.g. because it's unreachable, or the circuit breaker is open).
+    #[must_use]
+    pub fn failure_policy(mut self, policy: FallbackPolicy) -> Self {
+        self.failure_policy = policy;
+        self
+    }
+
+    /// HTTP status of the requests rejected by the failure policy (defaults to 503).
+    #[must_use]
+    pub fn failure_status(mut self, status: u16) -> Self {
+        self.failure_response.status = status;
+        self
+    }
+
+    /// Response to the denied requests (defaults to `401 Unauthorized`).
+    #[must_use]
+    pub fn deny_response(mut self, response: ResponseTemplate) -> Self {
+        self.deny_response = response;
+        self
+    }
+
+    /// Response to the requests rejected by the failure policy (defaults to `503 Service Unavailable`).
+    #[must_use]
+    pub fn failure_response(mut self, response: ResponseTemplate) -> Self {
+        self.failure_response = response;
+        self
+    }
+
+    /// When to stop calling an unresponsive server (defaults to 30 seconds after 5 consecutive failures).
+    #[must_use]
+    pub fn circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = config;
+        self
+    }
+
+    /// Forwards up to `limit` bytes of each request body to the server, so that body-based rules can be evaluated
+    /// (bodies are not inspected by default).
+    ///
+    /// The body is buffered only up to the limit, and is still delivered in full to the handlers.
+    #[must_use]
+    pub fn inspect_request_body(mut self, limit: usize) -> Self {
+        self.request_body_limit = Some(limit);
+        self
+    }
+
+    /// Captures up to `limit` bytes of each response body and submits them to `inspector`
+    /// (bodies are not inspected by default).
+    ///
+    /// Responses the inspector denies are replaced with the deny response;
+    /// the others are delivered unchanged, including streaming bodies.
+    /// The body is awaited for at most [`response_inspection_timeout`](Self::response_inspection_timeout),
+    /// so that streaming responses aren't held back.
+    #[must_use]
+    pub fn inspect_response_body(
+        mut self,
+        limit: usize,
+        inspector: impl ResponseBodyInspector + 'static,
+    ) -> Self {
+        self.response_inspection = Some(ResponseInspection {
+            limit,
+            timeout: self.response_inspection_timeout,
+            inspector: Arc::new(inspector),
+        });
+        self
+    }
+
+    /// How long response bodies are awaited for inspection (defaults to 1 second).
+    #[must_use]
+    pub fn response_inspection_timeout(mut self, timeout: Duration) -> Self {
+        self.response_inspection_timeout = timeout;
+        if let Some(inspection) = &mut self.response_inspection {
+            inspection.timeout = timeout;
+        }
+        self
+    }
+
+    /// Reports the responses to the server in the background, returning them to the clients immediately
+    /// (responses are reported before being returned by default).
+    ///
+    /// The server can then no longer block a response: its `Deny` verdicts only block
+    /// the following requests with the same cache key.
+    #[must_use]
+    pub fn report_responses_in_background(mut self, config: ResponseReportingConfig) -> Self {
+        self.response_reporting = Some(config);
+        self
+    }
+
+    /// Starts in monitor-only mode, logging and counting the requests that would be blocked
+    /// instead of blocking them (defaults to `false`).
+    ///
+    /// The mode can then be switched at runtime with [`Context::set_monitor_only`].
+    #[must_use]
+    pub fn monitor_only(mut self, monitor_only: bool) -> Self {
+        self.monitor_only = monitor_only;
+        self
+    }
+
+    /// Adds a rule exempting the matching requests from the firewall (there are no bypass rules by default).
+    ///
+    /// Rules are checked in the order they're added, and the first matching one applies.
+    #[must_use]
+    pub fn bypass(mut self, rule: BypassRule) -> Self {
+        self.bypass_rules.push(rule);
+        self
+    }
+
+    /// How the client IPs are resolved behind reverse proxies
+    /// (defaults to trusting no proxy, i.e. using the peer address).
+    #[must_use]
+    pub fn client_ip_resolver(mut self, resolver: ClientIpResolver) -> Self {
+        self.client_ip_resolver = resolver;
+        self
+    }
+
+    /// How [`build`](Self::build) waits for the client to be ready
+    /// (defaults to waiting with no deadline).
+    #[must_use]
+    pub fn startup_mode(mut self, startup_mode: StartupMode) -> Self {
+        self.startup_mode = startup_mode;
+        self
+    }
+
+    /// Settings of the firewall decisions cache (TTLs, capacity, sweeping, key policy, snapshot).
+    #[must_use]
+    pub fn cache_config(mut self, cache_config: CacheConfig) -> Self {
+        self.cache_config = cache_config;
+        self
+    }
+
+    /// Connects to the `AppGuard` server and, depending on the startup mode,
+    /// waits for the client to be authorized.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn build(self) -> Result<T, Error>
+    where
+        T: From<Context>,
+    {
+        let host = match self.address {
+            Some(address) => address,
+            None => std::env::var("CONTROL_SERVICE_ADDR").handle_err(location!())?,
+        };
+        let port = match self.port {
+            Some(port) => port,
+            None => {
+                let port_str = std::env::var("CONTROL_SERVICE_PORT").handle_err(location!())?;
+                port_str.parse::<u16>().handle_err(location!())?
+            }
+        };
+
+        let connect = AppGuardGrpcInterface::new(&host, port, self.tls);
+        let server = match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect)
+                .await
+                .handle_err(location!())?,
+            None => connect.await,
+        }
+        .handle_err(location!())?;
+
+        // the storage directory only hosts the snapshot if it was chosen, or if it holds the default store
+        let snapshot_in_storage_dir = self.storage_dir.is_some() || self.secret_store.is_none();
+        let storage_dir = self.storage_dir.unwrap_or_else(JsonFileStore::default_dir);
+        let snapshot_dir = snapshot_in_storage_dir.then(|| storage_dir.clone());
+        let mut cache_config = self.cache_config;
+        if let Some(snapshot) = &mut cache_config.snapshot
+            && snapshot.path.is_none()
+        {
+            match snapshot_dir {
+                Some(dir) => snapshot.path = Some(dir.join(CacheSnapshotConfig::FILE_NAME)),
+                None => {
+                    log::warn!(
+                        "Cache snapshot disabled: set its path when using a custom secret store"
+                    );
+                    cache_config.snapshot = None;
+                }
+            }
+        }
+
+        let store: Arc<dyn SecretStore> = match self.secret_store {
+            Some(store) => store,
+            None => Arc::new(
+                JsonFileStore::open(storage_dir, self.storage_key.or_else(StorageKey::from_env))
+                    .await?,
+            ),
+        };
+
+        let installation_code = match self
+            .installation_code
+            .or_else(|| std::env::var("INSTALLATION_CODE").ok())
+        {
+            Some(installation_code) => installation_code,
+            None => store
+                .get_value(Secret::InstallationCode)
+                .await
+                .ok_or("Installation code not set")
+                .handle_err(location!())?,
+        };
+        // the code is read back from storage every time the authorization flow is (re-)entered
+        if store.get_value(Secret::InstallationCode).await.as_ref() != Some(&installation_code) {
+            store
+                .set_value(Secret::InstallationCode, &installation_code)
+                .await?;
+        }
+
+        let control_channel = ControlChannelSettings {
+            r#type: self.client_type,
+            backoff: Backoff::new(self.reconnect_initial_delay, self.reconnect_max_delay),
+            device_identities: self.device_identities,
+            token_refresh_margin: self.token_refresh_margin,
+        };
+        let settings = ContextSettings {
+            fallback_policy: self.fallback_policy,
+            failure_policy: self.failure_policy,
+            deny_response: self.deny_response,
+            failure_response: self.failure_response,
+            circuit_breaker: self.circuit_breaker,
+            request_body_limit: self.request_body_limit,
+            response_inspection: self.response_inspection,
+            response_reporting: self.response_reporting,
+            monitor_only: self.monitor_only,
+            bypass_rules: self.bypass_rules,
+            client_ip_resolver: self.client_ip_resolver,
+            startup_mode: self.startup_mode,
+            cache_config,
+        };
+        let ctx = Context::start(server, store, control_channel, settings).await?;
+
+        Ok(T::from(ctx))
+    }
+}
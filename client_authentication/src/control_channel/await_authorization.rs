@@ -1,10 +1,10 @@
 use crate::control_channel::{InboundStream, OutboundStream};
-use crate::storage::{Secret, Storage};
+use crate::device_identity::{DeviceIdentity, resolve_device_uuid};
+use crate::storage::{Secret, SecretStore};
 use nullnet_libappguard::appguard_commands::{
     AuthorizationRequest, ClientMessage, client_message, server_message,
 };
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
-use smbioslib::{SMBiosSystemInformation, table_load_from_device};
 
 pub enum Verdict {
     Approved,
@@ -14,15 +14,12 @@ pub enum Verdict {
 pub async fn await_authorization(
     inbound: InboundStream,
     outbound: OutboundStream,
+    store: &dyn SecretStore,
     installation_code: impl Into<String>,
     r#type: String,
+    device_identities: &[DeviceIdentity],
 ) -> Result<Verdict, Error> {
-    let uuid = table_load_from_device()
-        .handle_err(location!())?
-        .find_map(|value: SMBiosSystemInformation| value.uuid())
-        .map(|uuid| uuid.to_string())
-        .ok_or("Failed to retrieve device UUID")
-        .handle_err(location!())?;
+    let uuid = resolve_device_uuid(device_identities, store).await?;
     let message = ClientMessage {
         message: Some(client_message::Message::AuthorizationRequest(
             AuthorizationRequest {
@@ -58,11 +55,11 @@ pub async fn await_authorization(
         match message {
             server_message::Message::DeviceAuthorized(data) => {
                 if let Some(app_id) = data.app_id {
-                    Storage::set_value(Secret::AppId, &app_id).await?;
+                    store.set_value(Secret::AppId, &app_id).await?;
                 }
 
                 if let Some(app_secret) = data.app_secret {
-                    Storage::set_value(Secret::AppSecret, &app_secret).await?;
+                    store.set_value(Secret::AppSecret, &app_secret).await?;
                 }
 
                 return Ok(Verdict::Approved);
@@ -0,0 +1,38 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Exponential backoff with jitter, used to pace control channel reconnections.
+#[derive(Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    /// Returns the delay to wait before the next attempt and doubles the base delay.
+    ///
+    /// The returned value is picked randomly in `[base / 2, base]`,
+    /// so that many clients restarting together don't reconnect in lockstep.
+    pub fn next_delay(&mut self) -> Duration {
+        let base = self.current;
+        self.current = (self.current * 2).min(self.max);
+
+        let half = base / 2;
+        let jitter = rand::rng().random_range(Duration::ZERO..=half);
+        half + jitter
+    }
+
+    /// Resets the base delay after a successful connection.
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
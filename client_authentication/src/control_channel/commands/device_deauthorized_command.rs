@@ -0,0 +1,31 @@
+use crate::client_state::ClientState;
+use crate::storage::Secret;
+use crate::{context::Context, control_channel::command::ExecutableCommand};
+
+pub struct DeviceDeauthorizedCommand {
+    context: Context,
+}
+
+impl DeviceDeauthorizedCommand {
+    pub fn new(context: Context) -> Self {
+        Self { context }
+    }
+}
+
+impl ExecutableCommand for DeviceDeauthorizedCommand {
+    async fn execute(self) -> Result<(), nullnet_liberror::Error> {
+        log::debug!("Received DeviceDeauthorizedCommand");
+        self.context.token_provider.clear().await;
+        self.context.set_state(ClientState::Deauthorized);
+        // the installation code has been consumed: a new one is needed to re-enter the authorization flow;
+        // the credentials go first, and every secret is deleted even if another one fails (returning the first error),
+        // so that a deauthorized device is never re-authenticated with stale credentials
+        let store = &self.context.store;
+        let mut result = Ok(());
+        for secret in [Secret::AppId, Secret::AppSecret, Secret::InstallationCode] {
+            let deleted = store.delete_value(secret).await;
+            result = result.and(deleted);
+        }
+        result
+    }
+}
@@ -0,0 +1,25 @@
+use crate::cache::CacheInvalidation;
+use crate::{context::Context, control_channel::command::ExecutableCommand};
+
+pub struct InvalidateCacheCommand {
+    context: Context,
+    invalidation: CacheInvalidation,
+}
+
+impl InvalidateCacheCommand {
+    pub fn new(context: Context, invalidation: CacheInvalidation) -> Self {
+        Self {
+            context,
+            invalidation,
+        }
+    }
+}
+
+impl ExecutableCommand for InvalidateCacheCommand {
+    async fn execute(self) -> Result<(), nullnet_liberror::Error> {
+        log::debug!("Received InvalidateCacheCommand: {:?}", self.invalidation);
+        let removed = self.context.cache.invalidate(&self.invalidation);
+        log::info!("Invalidated {removed} cache entries");
+        Ok(())
+    }
+}
@@ -1,7 +1,13 @@
+mod device_deauthorized_command;
 mod heartbeat_command;
+mod invalidate_cache_command;
 mod set_firewall_defaults_command;
+mod set_monitor_only_command;
 mod update_token_command;
 
+pub use device_deauthorized_command::*;
 pub use heartbeat_command::*;
+pub use invalidate_cache_command::*;
 pub use set_firewall_defaults_command::*;
+pub use set_monitor_only_command::*;
 pub use update_token_command::*;
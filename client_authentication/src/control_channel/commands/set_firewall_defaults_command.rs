@@ -1,4 +1,3 @@
-use crate::cache::Cache;
 use crate::{context::Context, control_channel::command::ExecutableCommand};
 use nullnet_libappguard::appguard_commands::FirewallDefaults;
 
@@ -17,7 +16,8 @@ impl ExecutableCommand for SetFirewallDefaultsCommand {
     async fn execute(self) -> Result<(), nullnet_liberror::Error> {
         log::debug!("Received SetFirewallDefaultsCommand");
         *self.context.firewall_defaults.lock().await = self.defaults;
-        *self.context.cache.lock().await = Cache::new(self.defaults);
+        self.context.cache.reset(self.defaults);
+        self.context.set_initialized();
         Ok(())
     }
 }
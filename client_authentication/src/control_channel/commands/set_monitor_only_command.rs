@@ -0,0 +1,20 @@
+use crate::{context::Context, control_channel::command::ExecutableCommand};
+
+pub struct SetMonitorOnlyCommand {
+    context: Context,
+    enabled: bool,
+}
+
+impl SetMonitorOnlyCommand {
+    pub fn new(context: Context, enabled: bool) -> Self {
+        Self { context, enabled }
+    }
+}
+
+impl ExecutableCommand for SetMonitorOnlyCommand {
+    async fn execute(self) -> Result<(), nullnet_liberror::Error> {
+        log::debug!("Received SetMonitorOnlyCommand: {}", self.enabled);
+        self.context.monitor_mode.set_enabled(self.enabled);
+        Ok(())
+    }
+}
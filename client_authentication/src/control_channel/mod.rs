@@ -1,24 +1,32 @@
+use crate::client_state::ClientState;
 use crate::context::Context;
 use crate::control_channel::command::ExecutableCommand;
 use crate::control_channel::commands::{
-    HeartbeatCommand, SetFirewallDefaultsCommand, UpdateTokenCommand,
+    DeviceDeauthorizedCommand, HeartbeatCommand, SetFirewallDefaultsCommand, UpdateTokenCommand,
 };
 use crate::control_channel::post_startup::post_startup;
-use crate::storage::{Secret, Storage};
+use crate::device_identity::DeviceIdentity;
+use crate::storage::Secret;
 use await_authorization::await_authorization;
+pub(crate) use backoff::Backoff;
 use nullnet_libappguard::Streaming;
 use nullnet_libappguard::appguard_commands::server_message::Message;
 use nullnet_libappguard::appguard_commands::{ClientMessage, ServerMessage, server_message};
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
 use send_authenticate::send_authenticate;
 use std::sync::Arc;
+use std::time::Duration;
+use token_refresh::{TokenEvent, TokenRefresh};
 use tokio::sync::{Mutex, mpsc};
+use tokio::task::JoinHandle;
 
 mod await_authorization;
-mod command;
-mod commands;
+mod backoff;
+pub(crate) mod command;
+pub(crate) mod commands;
 mod post_startup;
 mod send_authenticate;
+mod token_refresh;
 
 pub(crate) type InboundStream = Arc<Mutex<Streaming<ServerMessage>>>;
 pub(crate) type OutboundStream = Arc<Mutex<mpsc::Sender<ClientMessage>>>;
@@ -47,18 +55,55 @@ pub(crate) type OutboundStream = Arc<Mutex<mpsc::Sender<ClientMessage>>>;
 //     // }
 // }
 
+/// Settings of the control channel, provided when the context is built.
+pub(crate) struct ControlChannelSettings {
+    pub(crate) r#type: String,
+    pub(crate) backoff: Backoff,
+    pub(crate) device_identities: Vec<DeviceIdentity>,
+    pub(crate) token_refresh_margin: Duration,
+}
+
 pub async fn start_control_stream(
     context: Context,
-    installation_code: String,
-    r#type: String, // mut terminate: broadcast::Receiver<()>,
-) {
-    tokio::spawn(control_stream(context.clone(), installation_code, r#type));
+    settings: ControlChannelSettings, // mut terminate: broadcast::Receiver<()>,
+) -> JoinHandle<()> {
+    tokio::spawn(supervise_control_stream(context.clone(), settings))
+}
+
+/// Keeps the control channel alive for the whole lifetime of the client.
+///
+/// Whenever the stream is lost, a new one is opened after an exponential backoff,
+/// the client re-authenticates with the stored credentials,
+/// and commands processing is resumed.
+/// After a deauthorization or a rejected authorization request,
+/// the client stays idle until a new installation code is provided.
+async fn supervise_control_stream(context: Context, mut settings: ControlChannelSettings) {
+    loop {
+        match control_stream(&context, &mut settings).await {
+            Ok(()) => log::warn!("Control channel closed by the server"),
+            Err(err) => log::error!("Control channel failed: {}", err.to_str()),
+        }
+
+        let state = context.state();
+        if state.is_idle() {
+            log::warn!("Client {state:?}: waiting for a new installation code");
+            let mut state = context.subscribe_state();
+            if state.wait_for(|state| !state.is_idle()).await.is_err() {
+                return;
+            }
+            settings.backoff.reset();
+            continue;
+        }
+
+        let delay = settings.backoff.next_delay();
+        log::info!("Reconnecting control channel in {} ms", delay.as_millis());
+        tokio::time::sleep(delay).await;
+    }
 }
 
 async fn control_stream(
-    context: Context,
-    installation_code: String,
-    r#type: String,
+    context: &Context,
+    settings: &mut ControlChannelSettings,
 ) -> Result<(), Error> {
     let (outbound, receiver) = mpsc::channel(64);
     let inbound = context
@@ -70,25 +115,80 @@ async fn control_stream(
     let inbound = Arc::new(Mutex::new(inbound));
     let outbound = Arc::new(Mutex::new(outbound));
 
-    match await_authorization(inbound.clone(), outbound.clone(), installation_code, r#type).await? {
-        await_authorization::Verdict::Approved => {}
-        await_authorization::Verdict::Rejected => {
-            Err("Auhtorization has been rejected").handle_err(location!())?;
-            // Cleanup ??
-            // Remove ORG ID?
-            // Enter some other state or something?
+    let store = context.store.as_ref();
+    let has_credentials = store.get_value(Secret::AppId).await.is_some()
+        && store.get_value(Secret::AppSecret).await.is_some();
+
+    if !has_credentials {
+        let installation_code = store
+            .get_value(Secret::InstallationCode)
+            .await
+            .ok_or("Installation code not set")
+            .handle_err(location!())?;
+
+        context.set_state(ClientState::AwaitingApproval);
+        match await_authorization(
+            inbound.clone(),
+            outbound.clone(),
+            store,
+            installation_code,
+            settings.r#type.clone(),
+            &settings.device_identities,
+        )
+        .await?
+        {
+            await_authorization::Verdict::Approved => {}
+            await_authorization::Verdict::Rejected => {
+                // the same installation code would be rejected again
+                context.set_state(ClientState::Rejected);
+                Err("Authorization has been rejected").handle_err(location!())?;
+            }
         }
     }
 
     // Clone the outbound stream to keep it alive—closing it signals
     // an error to the server, which closes the connection.
-    send_authenticate(outbound.clone()).await?;
+    send_authenticate(outbound.clone(), store).await?;
+    // sending only queues the message: the client is authorized once the server sends a token
+    let mut authenticated = false;
 
     tokio::spawn(post_startup(context.clone()));
 
-    while let Ok(message) = inbound.lock().await.message().await {
+    let mut token_refresh = TokenRefresh::new(settings.token_refresh_margin);
+    // authenticating already requested a new token, so the stored one isn't refreshed again
+    if let Some(expires_at) = context.token_provider.expires_at() {
+        token_refresh.requested(expires_at);
+    }
+
+    loop {
+        let message = tokio::select! {
+            message = async { inbound.lock().await.message().await } => {
+                message.handle_err(location!())?
+            }
+            event = token_refresh.next_event(&context.token_provider) => {
+                match event {
+                    TokenEvent::RefreshDue(expires_at) => {
+                        log::warn!("Token about to expire without a server update: re-authenticating");
+                        token_refresh.requested(expires_at);
+                        send_authenticate(outbound.clone(), store).await?;
+                    }
+                    TokenEvent::Expired => {
+                        // the fallback policy applies until a new token is received
+                        context.token_provider.clear().await;
+                        Err("Token expired without being renewed").handle_err(location!())?;
+                    }
+                }
+                continue;
+            }
+        };
+
+        let Some(message) = message else {
+            // The server gracefully ended the stream
+            return Ok(());
+        };
+
         let message = message
-            .and_then(|message| message.message)
+            .message
             .ok_or("Malformed message")
             .handle_err(location!())?;
 
@@ -98,6 +198,10 @@ async fn control_stream(
 
                 if let Err(err) = cmd.execute().await {
                     log::error!("UpdateTokenCommand execution failed: {}", err.to_str());
+                } else if !authenticated {
+                    authenticated = true;
+                    context.set_state(ClientState::Authorized);
+                    settings.backoff.reset();
                 }
             }
             server_message::Message::Heartbeat(()) => {
@@ -108,11 +212,17 @@ async fn control_stream(
                 }
             }
             server_message::Message::DeviceDeauthorized(()) => {
-                // // @TODO: Command
-                _ = Storage::delete_value(Secret::AppId).await;
-                _ = Storage::delete_value(Secret::AppSecret).await;
-                // // Gracefuly transition to IDLE state
-                todo!();
+                let cmd = DeviceDeauthorizedCommand::new(context.clone());
+
+                if let Err(err) = cmd.execute().await {
+                    log::error!(
+                        "DeviceDeauthorizedCommand execution failed: {}",
+                        err.to_str()
+                    );
+                }
+
+                // Gracefully transition to the idle state
+                return Ok(());
             }
             server_message::Message::AuthorizationRejected(()) => {
                 Err("Unexpected message").handle_err(location!())?;
@@ -132,6 +242,4 @@ async fn control_stream(
             }
         }
     }
-
-    Ok(())
 }
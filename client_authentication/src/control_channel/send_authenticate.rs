@@ -1,15 +1,20 @@
 use crate::control_channel::OutboundStream;
-use crate::storage::{Secret, Storage};
+use crate::storage::{Secret, SecretStore};
 use nullnet_libappguard::appguard_commands::{Authentication, ClientMessage, client_message};
 use nullnet_liberror::{Error, ErrorHandler, Location, location};
 
-pub async fn send_authenticate(outbound: OutboundStream) -> Result<(), Error> {
-    let app_id = Storage::get_value(Secret::AppId)
+pub async fn send_authenticate(
+    outbound: OutboundStream,
+    store: &dyn SecretStore,
+) -> Result<(), Error> {
+    let app_id = store
+        .get_value(Secret::AppId)
         .await
         .ok_or("AppId not set")
         .handle_err(location!())?;
 
-    let app_secret = Storage::get_value(Secret::AppSecret)
+    let app_secret = store
+        .get_value(Secret::AppSecret)
         .await
         .ok_or("AppSecret not set")
         .handle_err(location!())?;
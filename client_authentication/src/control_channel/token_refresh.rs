@@ -0,0 +1,60 @@
+use crate::token_provider::TokenProvider;
+use std::time::{Duration, SystemTime};
+
+pub enum TokenEvent {
+    /// The token is about to expire and no new one has been pushed by the server yet.
+    RefreshDue(SystemTime),
+    /// The token has expired even though a refresh was requested.
+    Expired,
+}
+
+/// Keeps track of the expiration of the current token.
+pub struct TokenRefresh {
+    margin: Duration,
+    /// Expiration of the token a replacement was requested for,
+    /// and when it's considered expired without having been renewed.
+    requested_for: Option<(SystemTime, SystemTime)>,
+}
+
+impl TokenRefresh {
+    pub fn new(margin: Duration) -> Self {
+        Self {
+            margin,
+            requested_for: None,
+        }
+    }
+
+    /// Waits until the current token needs to be refreshed, or has expired.
+    ///
+    /// Never completes if the token expiration is unknown.
+    pub async fn next_event(&self, token_provider: &TokenProvider) -> TokenEvent {
+        let Some(expires_at) = token_provider.expires_at() else {
+            return std::future::pending().await;
+        };
+
+        if let Some((requested_for, deadline)) = self.requested_for
+            && requested_for == expires_at
+        {
+            tokio::time::sleep(time_until(deadline)).await;
+            TokenEvent::Expired
+        } else {
+            let refresh_at = expires_at.checked_sub(self.margin).unwrap_or(expires_at);
+            tokio::time::sleep(time_until(refresh_at)).await;
+            TokenEvent::RefreshDue(expires_at)
+        }
+    }
+
+    /// Records that a new token was requested to replace the one expiring at `expires_at`.
+    ///
+    /// The server is given at least the refresh margin to send it, even if the token already expired.
+    pub fn requested(&mut self, expires_at: SystemTime) {
+        let deadline = expires_at.max(SystemTime::now() + self.margin);
+        self.requested_for = Some((expires_at, deadline));
+    }
+}
+
+fn time_until(instant: SystemTime) -> Duration {
+    instant
+        .duration_since(SystemTime::now())
+        .unwrap_or(Duration::ZERO)
+}
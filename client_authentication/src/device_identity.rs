@@ -0,0 +1,76 @@
+use nullnet_liberror::{Error, ErrorHandler, Location, location};
+use smbioslib::{SMBiosSystemInformation, table_load_from_device};
+use uuid::Uuid;
+
+use crate::storage::{Secret, SecretStore};
+
+/// Source of the device UUID sent to the server when requesting authorization.
+///
+/// Sources are tried in order, until one of them succeeds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceIdentity {
+    /// System UUID read from the SMBIOS tables (usually requires root privileges).
+    Smbios,
+    /// Machine ID read from `/etc/machine-id` (or `/var/lib/dbus/machine-id`).
+    MachineId,
+    /// Random UUID generated on first use and persisted in the secret store.
+    Generated,
+    /// Explicitly configured value.
+    Explicit(String),
+}
+
+impl DeviceIdentity {
+    /// Default chain: SMBIOS, then machine ID, then a generated UUID.
+    #[must_use]
+    pub fn default_chain() -> Vec<DeviceIdentity> {
+        vec![
+            DeviceIdentity::Smbios,
+            DeviceIdentity::MachineId,
+            DeviceIdentity::Generated,
+        ]
+    }
+
+    async fn resolve(&self, store: &dyn SecretStore) -> Option<String> {
+        match self {
+            DeviceIdentity::Smbios => table_load_from_device()
+                .ok()?
+                .find_map(|value: SMBiosSystemInformation| value.uuid())
+                .map(|uuid| uuid.to_string()),
+            DeviceIdentity::MachineId => {
+                for path in ["/etc/machine-id", "/var/lib/dbus/machine-id"] {
+                    if let Ok(id) = tokio::fs::read_to_string(path).await
+                        && let Ok(uuid) = Uuid::parse_str(id.trim())
+                    {
+                        return Some(uuid.to_string());
+                    }
+                }
+                None
+            }
+            DeviceIdentity::Generated => {
+                if let Some(uuid) = store.get_value(Secret::DeviceUuid).await {
+                    return Some(uuid);
+                }
+                let uuid = Uuid::new_v4().to_string();
+                store.set_value(Secret::DeviceUuid, &uuid).await.ok()?;
+                Some(uuid)
+            }
+            DeviceIdentity::Explicit(value) => Some(value.clone()),
+        }
+    }
+}
+
+/// Returns the device UUID provided by the first source of `chain` that succeeds.
+pub(crate) async fn resolve_device_uuid(
+    chain: &[DeviceIdentity],
+    store: &dyn SecretStore,
+) -> Result<String, Error> {
+    for identity in chain {
+        if let Some(uuid) = identity.resolve(store).await {
+            log::debug!("Device UUID obtained from {identity:?}");
+            return Ok(uuid);
+        }
+        log::debug!("Device UUID not available from {identity:?}");
+    }
+
+    Err("Failed to retrieve device UUID").handle_err(location!())
+}
@@ -0,0 +1,27 @@
+use nullnet_libappguard::appguard_commands::{FirewallDefaults, FirewallPolicy};
+
+/// Policy applied by the middlewares when the `AppGuard` server can't be consulted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FallbackPolicy {
+    /// Forward every request.
+    Allow,
+    /// Reject every request.
+    Deny,
+    /// Apply the default policy of the last known firewall defaults.
+    #[default]
+    FirewallDefaults,
+}
+
+impl FallbackPolicy {
+    /// Resolves this fallback to a concrete firewall policy.
+    #[must_use]
+    pub fn resolve(self, defaults: &FirewallDefaults) -> FirewallPolicy {
+        match self {
+            FallbackPolicy::Allow => FirewallPolicy::Allow,
+            FallbackPolicy::Deny => FirewallPolicy::Deny,
+            FallbackPolicy::FirewallDefaults => {
+                FirewallPolicy::try_from(defaults.policy).unwrap_or_default()
+            }
+        }
+    }
+}
@@ -1,7 +1,39 @@
+mod bypass;
 mod cache;
+mod cache_key;
+mod cache_snapshot;
+mod circuit_breaker;
+mod client_ip;
+mod client_state;
 mod context;
+mod context_builder;
 mod control_channel;
+mod device_identity;
+mod fallback_policy;
+mod monitor_mode;
+mod response_inspection;
+mod response_reporter;
+mod response_template;
+mod startup_mode;
 mod storage;
 mod token_provider;
-pub use cache::CacheKey;
+pub use bypass::{BypassAction, BypassRequest, BypassRule};
+pub use cache::{Cache, CacheConfig, CacheInvalidation, CacheStats, CachedDecision};
+pub use cache_key::{CacheKey, CacheKeyPolicy, CacheKeySource};
+pub use cache_snapshot::CacheSnapshotConfig;
+pub use circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+pub use client_ip::{ClientIpResolver, ClientIpSource, ForwardedHeader, ProxyProtocolAddress};
+pub use client_state::ClientState;
 pub use context::Context;
+pub use context_builder::ContextBuilder;
+pub use device_identity::DeviceIdentity;
+pub use fallback_policy::FallbackPolicy;
+pub use monitor_mode::MonitorMode;
+pub use response_inspection::{ResponseBodyInspector, ResponseInspection};
+pub use response_reporter::{ResponseReport, ResponseReporter, ResponseReportingConfig};
+pub use response_template::{RenderedResponse, ResponseTemplate};
+pub use startup_mode::StartupMode;
+pub use storage::{
+    DirectoryStore, JsonFileStore, MemoryStore, Secret, SecretStore, StorageKey, StoreFuture,
+};
+pub use token_provider::{RetrievalStrategy, TokenProvider};
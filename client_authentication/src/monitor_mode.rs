@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Monitor-only (shadow) enforcement mode.
+///
+/// While it is enabled, the middlewares keep reporting the traffic and computing the verdicts,
+/// but forward every request: the ones that would have been blocked are only logged and counted.
+#[derive(Debug, Default)]
+pub struct MonitorMode {
+    enabled: AtomicBool,
+    would_block: AtomicU64,
+}
+
+impl MonitorMode {
+    #[must_use]
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            would_block: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns `true` if the verdicts are not enforced.
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        let was_enabled = self.enabled.swap(enabled, Ordering::Relaxed);
+        if was_enabled != enabled {
+            log::info!(
+                "Monitor-only mode {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+        }
+    }
+
+    /// Returns the number of requests that would have been blocked while monitor-only mode was enabled.
+    #[must_use]
+    pub fn would_block_count(&self) -> u64 {
+        self.would_block.load(Ordering::Relaxed)
+    }
+
+    /// Called when a request should be blocked because of `reason`:
+    /// returns `true` if it must actually be blocked,
+    /// or logs and counts it and returns `false` in monitor-only mode.
+    ///
+    /// `monitored` requests matched a [`BypassAction::Monitor`](crate::BypassAction::Monitor) rule, and are never blocked either.
+    pub fn should_block(&self, monitored: bool, reason: &str) -> bool {
+        if !monitored && !self.is_enabled() {
+            return true;
+        }
+        self.would_block.fetch_add(1, Ordering::Relaxed);
+        log::warn!("Monitor-only mode: request would have been blocked ({reason})");
+        false
+    }
+}
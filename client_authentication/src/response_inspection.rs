@@ -0,0 +1,29 @@
+use nullnet_libappguard::appguard_commands::FirewallPolicy;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Inspects the beginning of response bodies before they are sent to clients,
+/// e.g. to prevent leaks of stack traces, credit card numbers or secrets.
+pub trait ResponseBodyInspector: Send + Sync {
+    /// Returns [`FirewallPolicy::Deny`] to replace the response with the deny response.
+    fn inspect(&self, status: u16, body: &[u8]) -> FirewallPolicy;
+}
+
+impl<F> ResponseBodyInspector for F
+where
+    F: Fn(u16, &[u8]) -> FirewallPolicy + Send + Sync,
+{
+    fn inspect(&self, status: u16, body: &[u8]) -> FirewallPolicy {
+        self(status, body)
+    }
+}
+
+/// Settings of the response body inspection.
+#[derive(Clone)]
+pub struct ResponseInspection {
+    /// Maximum number of body bytes captured from each response.
+    pub limit: usize,
+    /// How long the body is awaited: streaming responses are inspected on what arrived in the meantime.
+    pub timeout: Duration,
+    pub inspector: Arc<dyn ResponseBodyInspector>,
+}
@@ -0,0 +1,131 @@
+use crate::cache::Cache;
+use crate::cache_key::CacheKey;
+use crate::circuit_breaker::CircuitBreaker;
+use nullnet_libappguard::AppGuardGrpcInterface;
+use nullnet_libappguard::appguard::AppGuardHttpResponse;
+use nullnet_libappguard::appguard_commands::{FirewallDefaults, FirewallPolicy};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{Mutex, mpsc};
+use tokio::task::JoinSet;
+
+/// Settings of the asynchronous reporting of the responses to the `AppGuard` server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseReportingConfig {
+    /// Maximum number of responses waiting to be reported; further responses are not reported.
+    pub capacity: usize,
+    /// Maximum number of responses reported to the server at once.
+    pub batch_size: usize,
+}
+
+impl Default for ResponseReportingConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            batch_size: 32,
+        }
+    }
+}
+
+/// A response waiting to be reported to the server.
+pub struct ResponseReport {
+    pub response: AppGuardHttpResponse,
+    /// Key under which the verdict is cached, or `None` if it isn't.
+    pub cache_key: Option<CacheKey>,
+    /// Whether the request was denied (but forwarded in monitor-only mode).
+    pub request_denied: bool,
+}
+
+/// Reports the responses to the server in the background, so that they're returned to the clients immediately.
+///
+/// Since a response is already sent when the server checks it,
+/// a `Deny` verdict can't block it anymore: it's only cached,
+/// so that the following requests with the same cache key are blocked.
+pub struct ResponseReporter {
+    sender: mpsc::Sender<ResponseReport>,
+    dropped: AtomicU64,
+}
+
+impl ResponseReporter {
+    pub(crate) fn start(
+        config: ResponseReportingConfig,
+        server: AppGuardGrpcInterface,
+        firewall_defaults: Arc<Mutex<FirewallDefaults>>,
+        cache: Arc<Cache>,
+        circuit_breaker: Arc<CircuitBreaker>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(config.capacity.max(1));
+        tokio::spawn(report_batches(
+            receiver,
+            config.batch_size.max(1),
+            server,
+            firewall_defaults,
+            cache,
+            circuit_breaker,
+        ));
+        Self {
+            sender,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Enqueues a response to be reported, without waiting.
+    ///
+    /// If the queue is full the response is not reported, and is counted as dropped.
+    pub fn report(&self, report: ResponseReport) {
+        if self.sender.try_send(report).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            log::warn!("Response reporting queue full: response not reported ({dropped} so far)");
+        }
+    }
+
+    /// Returns the number of responses that couldn't be reported because the queue was full.
+    #[must_use]
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Sends the queued responses to the server, a batch of concurrent calls at a time,
+/// until the reporter is dropped.
+async fn report_batches(
+    mut receiver: mpsc::Receiver<ResponseReport>,
+    batch_size: usize,
+    server: AppGuardGrpcInterface,
+    firewall_defaults: Arc<Mutex<FirewallDefaults>>,
+    cache: Arc<Cache>,
+    circuit_breaker: Arc<CircuitBreaker>,
+) {
+    let mut batch = Vec::with_capacity(batch_size);
+    while receiver.recv_many(&mut batch, batch_size).await > 0 {
+        let fw_defaults = *firewall_defaults.lock().await;
+        let timeout = fw_defaults.timeout;
+        let default_policy = FirewallPolicy::try_from(fw_defaults.policy).unwrap_or_default();
+
+        let mut calls = JoinSet::new();
+        for report in batch.drain(..) {
+            let mut server = server.clone();
+            let cache = cache.clone();
+            let circuit_breaker = circuit_breaker.clone();
+            calls.spawn(async move {
+                let res = circuit_breaker
+                    .call(server.handle_http_response(timeout, default_policy, report.response))
+                    .await;
+                // a failed report is lost: the response was already returned anyway
+                let Some(res) = res else {
+                    return;
+                };
+                let policy = FirewallPolicy::try_from(res.policy).unwrap_or_default();
+                let policy = if report.request_denied || policy == FirewallPolicy::Deny {
+                    FirewallPolicy::Deny
+                } else {
+                    FirewallPolicy::Allow
+                };
+                if let Some(cache_key) = report.cache_key {
+                    cache.insert(cache_key, policy);
+                }
+            });
+        }
+        calls.join_all().await;
+    }
+}
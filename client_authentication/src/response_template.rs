@@ -0,0 +1,88 @@
+use uuid::Uuid;
+
+/// Response sent by the middlewares when they reject a request.
+///
+/// The body can contain the placeholders `{status}` and `{request_id}`;
+/// a new request ID is generated for each rejection and logged, so that it can be correlated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseTemplate {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// A [`ResponseTemplate`] with its placeholders filled in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl ResponseTemplate {
+    /// Plain text response.
+    #[must_use]
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self::with_content_type(status, "text/plain; charset=utf-8", body)
+    }
+
+    /// HTML response.
+    #[must_use]
+    pub fn html(status: u16, body: impl Into<String>) -> Self {
+        Self::with_content_type(status, "text/html; charset=utf-8", body)
+    }
+
+    /// JSON response (e.g. `{"error": "forbidden", "request_id": "{request_id}"}`).
+    #[must_use]
+    pub fn json(status: u16, body: impl Into<String>) -> Self {
+        Self::with_content_type(status, "application/json", body)
+    }
+
+    /// Redirect to another location (`302 Found`).
+    #[must_use]
+    pub fn redirect(location: impl Into<String>) -> Self {
+        Self {
+            status: 302,
+            headers: vec![("Location".to_string(), location.into())],
+            body: String::new(),
+        }
+    }
+
+    /// Adds a header to the response (e.g. `Retry-After`).
+    #[must_use]
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Fills in the placeholders of the template.
+    #[must_use]
+    pub fn render(&self) -> RenderedResponse {
+        let request_id = Uuid::new_v4().to_string();
+        log::debug!("Rejecting request {request_id} with status {}", self.status);
+
+        let status = self.status.to_string();
+        let fill = |s: &str| {
+            s.replace("{status}", &status)
+                .replace("{request_id}", &request_id)
+        };
+
+        RenderedResponse {
+            status: self.status,
+            headers: self
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), fill(value)))
+                .collect(),
+            body: fill(&self.body),
+        }
+    }
+
+    fn with_content_type(status: u16, content_type: &str, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: body.into(),
+        }
+    }
+}
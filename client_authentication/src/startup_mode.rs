@@ -0,0 +1,18 @@
+use std::time::Duration;
+
+/// How a context waits for the client to be ready when it's built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupMode {
+    /// Wait until the client is authorized and the firewall defaults are received,
+    /// failing if that doesn't happen before the given deadline (if any).
+    Blocking(Option<Duration>),
+    /// Return immediately and complete the startup in background;
+    /// in the meantime, the middlewares apply the fallback policy.
+    Background,
+}
+
+impl Default for StartupMode {
+    fn default() -> Self {
+        StartupMode::Blocking(None)
+    }
+}
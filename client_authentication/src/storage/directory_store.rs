@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+use nullnet_liberror::{Error, ErrorHandler, Location, location};
+
+use crate::storage::file_utils::write_atomically;
+use crate::storage::{Secret, SecretStore, StoreFuture};
+
+/// Store keeping each secret in its own file inside a directory
+/// (e.g. `<dir>/AppId`), compatible with Kubernetes secret mounts.
+///
+/// Files are read on every access, so that rotated secrets are picked up.
+pub struct DirectoryStore {
+    dir: PathBuf,
+}
+
+impl DirectoryStore {
+    #[must_use]
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn file_path(&self, secret: Secret) -> PathBuf {
+        self.dir.join(secret.as_str())
+    }
+}
+
+impl SecretStore for DirectoryStore {
+    fn get_value(&self, secret: Secret) -> StoreFuture<'_, Option<String>> {
+        Box::pin(async move {
+            let value = tokio::fs::read_to_string(self.file_path(secret))
+                .await
+                .ok()?;
+            let value = value.trim();
+            if value.is_empty() {
+                None
+            } else {
+                Some(value.to_string())
+            }
+        })
+    }
+
+    fn set_value<'a>(
+        &'a self,
+        secret: Secret,
+        value: &'a str,
+    ) -> StoreFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.dir)
+                .await
+                .handle_err(location!())?;
+            write_atomically(&self.file_path(secret), value.as_bytes()).await
+        })
+    }
+
+    fn delete_value(&self, secret: Secret) -> StoreFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.file_path(secret)).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    Err(err).handle_err(location!())
+                }
+                _ => Ok(()),
+            }
+        })
+    }
+}
@@ -0,0 +1,112 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::Argon2;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use nullnet_liberror::{Error, ErrorHandler, Location, location};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const SALT_LEN: usize = 16;
+
+/// Secret used to encrypt the client credentials at rest.
+#[derive(Clone)]
+pub enum StorageKey {
+    /// Key derived from a passphrase.
+    Passphrase(String),
+    /// Key derived from the content of a file.
+    KeyFile(PathBuf),
+}
+
+impl StorageKey {
+    /// Reads the storage key from `APPGUARD_STORAGE_PASSPHRASE` or `APPGUARD_STORAGE_KEY_FILE`.
+    pub(crate) fn from_env() -> Option<Self> {
+        std::env::var("APPGUARD_STORAGE_PASSPHRASE")
+            .ok()
+            .map(StorageKey::Passphrase)
+            .or_else(|| {
+                std::env::var("APPGUARD_STORAGE_KEY_FILE")
+                    .ok()
+                    .map(|path| StorageKey::KeyFile(path.into()))
+            })
+    }
+
+    async fn material(&self) -> Result<Vec<u8>, Error> {
+        match self {
+            StorageKey::Passphrase(passphrase) => Ok(passphrase.as_bytes().to_vec()),
+            StorageKey::KeyFile(path) => {
+                let content = tokio::fs::read(path).await.handle_err(location!())?;
+                Ok(content.trim_ascii().to_vec())
+            }
+        }
+    }
+}
+
+/// On-disk representation of an encrypted store.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct EncryptedConfigStore {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl EncryptedConfigStore {
+    pub(crate) fn salt(&self) -> Result<Vec<u8>, Error> {
+        STANDARD.decode(&self.salt).handle_err(location!())
+    }
+}
+
+pub(crate) struct Cipher {
+    cipher: Aes256Gcm,
+    salt: Vec<u8>,
+}
+
+impl Cipher {
+    /// Derives the encryption key; a new random salt is generated if none is provided.
+    pub(crate) async fn new(key: &StorageKey, salt: Option<Vec<u8>>) -> Result<Self, Error> {
+        let salt = salt.unwrap_or_else(|| {
+            let mut salt = vec![0; SALT_LEN];
+            rand::rng().fill_bytes(&mut salt);
+            salt
+        });
+
+        let mut derived = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(&key.material().await?, &salt, &mut derived)
+            .handle_err(location!())?;
+
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&derived)),
+            salt,
+        })
+    }
+
+    pub(crate) fn encrypt(&self, plaintext: &[u8]) -> Result<EncryptedConfigStore, Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .handle_err(location!())?;
+
+        Ok(EncryptedConfigStore {
+            salt: STANDARD.encode(&self.salt),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        })
+    }
+
+    pub(crate) fn decrypt(&self, store: &EncryptedConfigStore) -> Result<Vec<u8>, Error> {
+        let nonce = STANDARD.decode(&store.nonce).handle_err(location!())?;
+        let ciphertext = STANDARD.decode(&store.ciphertext).handle_err(location!())?;
+
+        if nonce.len() != 12 {
+            return Err("Malformed nonce in encrypted storage").handle_err(location!());
+        }
+
+        self.cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| "Failed to decrypt storage (wrong key?)")
+            .handle_err(location!())
+    }
+}
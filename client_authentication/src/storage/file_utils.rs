@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+use nullnet_liberror::{Error, ErrorHandler, Location, location};
+
+/// Advisory lock on a file, used to serialize writes among processes sharing a store.
+///
+/// The lock is released when this value is dropped.
+pub(crate) struct FileLock {
+    _file: std::fs::File,
+}
+
+impl FileLock {
+    pub(crate) async fn acquire(path: PathBuf) -> Result<Self, Error> {
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(false)
+                .open(&path)?;
+            file.lock()?;
+            Ok::<_, std::io::Error>(FileLock { _file: file })
+        })
+        .await
+        .handle_err(location!())?
+        .handle_err(location!())
+    }
+}
+
+/// Writes `contents` to `path` atomically, so that a crash can't leave a partially written file.
+///
+/// The data is written to a temporary file in the same directory, which then replaces `path`;
+/// the file is only accessible by its owner.
+pub(crate) async fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let file_name = path
+        .file_name()
+        .ok_or("Invalid storage file path")
+        .handle_err(location!())?
+        .to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{file_name}.tmp"));
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(&tmp_path).await.handle_err(location!())?;
+
+    // the mode only applies to newly created files
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .await
+            .handle_err(location!())?;
+    }
+
+    file.write_all(contents).await.handle_err(location!())?;
+    file.sync_all().await.handle_err(location!())?;
+    drop(file);
+
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .handle_err(location!())?;
+
+    // persist the rename itself
+    #[cfg(unix)]
+    if let Some(dir) = path.parent()
+        && let Ok(dir) = tokio::fs::File::open(dir).await
+    {
+        let _ = dir.sync_all().await;
+    }
+
+    Ok(())
+}
+
+/// Copies a corrupted file aside, so that its content isn't lost when the store is rewritten.
+pub(crate) async fn backup_corrupted(path: &Path) -> Result<PathBuf, Error> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let file_name = path
+        .file_name()
+        .ok_or("Invalid storage file path")
+        .handle_err(location!())?
+        .to_string_lossy();
+    let backup_path = path.with_file_name(format!("{file_name}.corrupted-{timestamp}"));
+
+    tokio::fs::copy(path, &backup_path)
+        .await
+        .handle_err(location!())?;
+
+    Ok(backup_path)
+}
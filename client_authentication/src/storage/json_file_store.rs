@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::{create_dir_all, read_to_string};
+use tokio::sync::Mutex;
+
+use dirs::config_dir;
+use serde::{Deserialize, Serialize};
+
+use nullnet_liberror::{Error, ErrorHandler, Location, location};
+
+use crate::storage::encryption::{Cipher, EncryptedConfigStore};
+use crate::storage::file_utils::{FileLock, backup_corrupted, write_atomically};
+use crate::storage::{Secret, SecretStore, StorageKey, StoreFuture};
+
+#[derive(Serialize, Deserialize, Default)]
+struct ConfigStore {
+    values: HashMap<String, String>,
+}
+
+/// Store keeping all the secrets in a single JSON file,
+/// optionally encrypted with a [`StorageKey`].
+///
+/// Writes are atomic and serialized among processes through an advisory lock,
+/// so that several clients on the same host can share the file.
+pub struct JsonFileStore {
+    file_path: PathBuf,
+    lock_path: PathBuf,
+    config: Mutex<ConfigStore>,
+    cipher: Option<Cipher>,
+}
+
+impl JsonFileStore {
+    const FILE_NAME: &'static str = "config.json";
+    const LOCK_FILE_NAME: &'static str = "config.json.lock";
+
+    /// Default storage directory: `<config dir>/appguard`.
+    #[must_use]
+    pub fn default_dir() -> PathBuf {
+        let mut path = config_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("appguard");
+        path
+    }
+
+    /// Loads the store from `<dir>/config.json`.
+    ///
+    /// When a key is provided, the store is encrypted at rest;
+    /// an existing plaintext store is transparently migrated to the encrypted format.
+    ///
+    /// If the file is corrupted, a backup copy is made and an error is returned.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn open(dir: impl Into<PathBuf>, key: Option<StorageKey>) -> Result<Self, Error> {
+        let dir = dir.into();
+        let file_path = dir.join(Self::FILE_NAME);
+        let lock_path = dir.join(Self::LOCK_FILE_NAME);
+
+        create_dir_all(&dir).await.handle_err(location!())?;
+
+        let _lock = FileLock::acquire(lock_path.clone()).await?;
+
+        let content = read_if_exists(&file_path).await?;
+
+        let (config, cipher) = match content {
+            None => {
+                let cipher = match key {
+                    Some(key) => Some(Cipher::new(&key, None).await?),
+                    None => None,
+                };
+                (ConfigStore::default(), cipher)
+            }
+            Some(content) => {
+                if let Ok(encrypted) = serde_json::from_str::<EncryptedConfigStore>(&content) {
+                    let key = key
+                        .ok_or("Storage is encrypted but no key was provided")
+                        .handle_err(location!())?;
+                    let cipher = Cipher::new(&key, Some(encrypted.salt()?)).await?;
+                    let config = decode(&file_path, &content, Some(&cipher)).await?;
+                    (config, Some(cipher))
+                } else {
+                    let config = decode(&file_path, &content, None).await?;
+                    let cipher = match key {
+                        Some(key) => {
+                            log::info!("Migrating plaintext storage to the encrypted format");
+                            Some(Cipher::new(&key, None).await?)
+                        }
+                        None => None,
+                    };
+                    (config, cipher)
+                }
+            }
+        };
+
+        if cipher.is_none() {
+            log::warn!("No storage key provided: credentials are stored unencrypted");
+        }
+
+        let store = Self {
+            file_path,
+            lock_path,
+            config: Mutex::new(config),
+            cipher,
+        };
+        // (re)write the file to apply encryption and restrictive permissions
+        store.persist(&*store.config.lock().await).await?;
+
+        Ok(store)
+    }
+
+    /// Applies `change` to the store, on top of the latest content written by any process.
+    async fn modify(&self, change: impl FnOnce(&mut ConfigStore)) -> Result<(), Error> {
+        let mut config = self.config.lock().await;
+        let _lock = FileLock::acquire(self.lock_path.clone()).await?;
+
+        if let Some(content) = read_if_exists(&self.file_path).await? {
+            *config = decode(&self.file_path, &content, self.cipher.as_ref()).await?;
+        }
+
+        change(&mut config);
+        self.persist(&config).await
+    }
+
+    async fn persist(&self, config: &ConfigStore) -> Result<(), Error> {
+        let json = match &self.cipher {
+            Some(cipher) => {
+                let plaintext = serde_json::to_vec(config).handle_err(location!())?;
+                serde_json::to_string_pretty(&cipher.encrypt(&plaintext)?)
+            }
+            None => serde_json::to_string_pretty(config),
+        }
+        .handle_err(location!())?;
+
+        write_atomically(&self.file_path, json.as_bytes()).await
+    }
+}
+
+impl SecretStore for JsonFileStore {
+    fn get_value(&self, secret: Secret) -> StoreFuture<'_, Option<String>> {
+        Box::pin(async move {
+            let config = self.config.lock().await;
+            let val = config.values.get(secret.as_str()).cloned();
+            val.and_then(|v| if v.is_empty() { None } else { Some(v) })
+        })
+    }
+
+    fn set_value<'a>(
+        &'a self,
+        secret: Secret,
+        value: &'a str,
+    ) -> StoreFuture<'a, Result<(), Error>> {
+        Box::pin(self.modify(move |config| {
+            config.values.insert(secret.as_str().into(), value.into());
+        }))
+    }
+
+    fn delete_value(&self, secret: Secret) -> StoreFuture<'_, Result<(), Error>> {
+        Box::pin(self.modify(move |config| {
+            config.values.remove(secret.as_str());
+        }))
+    }
+}
+
+async fn read_if_exists(path: &Path) -> Result<Option<String>, Error> {
+    match read_to_string(path).await {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).handle_err(location!()),
+    }
+}
+
+/// Parses the content of the store file, decrypting it if needed.
+///
+/// Unreadable content is backed up and reported as an error instead of being silently discarded.
+async fn decode(path: &Path, content: &str, cipher: Option<&Cipher>) -> Result<ConfigStore, Error> {
+    let config = match serde_json::from_str::<EncryptedConfigStore>(content) {
+        Ok(encrypted) => {
+            let cipher = cipher
+                .ok_or("Storage is encrypted but no key was provided")
+                .handle_err(location!())?;
+            // decryption failures are most likely caused by a wrong key: don't treat them as corruption
+            let plaintext = cipher.decrypt(&encrypted)?;
+            serde_json::from_slice::<ConfigStore>(&plaintext).ok()
+        }
+        Err(_) => serde_json::from_str::<ConfigStore>(content).ok(),
+    };
+
+    match config {
+        Some(config) => Ok(config),
+        None => {
+            let backup_path = backup_corrupted(path).await?;
+            Err(format!(
+                "Storage file {} is corrupted (backup saved to {})",
+                path.display(),
+                backup_path.display()
+            ))
+            .handle_err(location!())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn key(passphrase: &str) -> Option<StorageKey> {
+        Some(StorageKey::Passphrase(passphrase.to_string()))
+    }
+
+    fn backups(dir: &TempDir) -> Vec<PathBuf> {
+        std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().contains(".corrupted-"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn plaintext_store_is_migrated_to_encrypted() {
+        let dir = TempDir::new().unwrap();
+        let file_path = dir.path().join(JsonFileStore::FILE_NAME);
+        std::fs::write(&file_path, r#"{"values":{"AppId":"app-id"}}"#).unwrap();
+
+        let store = JsonFileStore::open(dir.path(), key("passphrase"))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_value(Secret::AppId).await.as_deref(),
+            Some("app-id")
+        );
+
+        let content = std::fs::read_to_string(&file_path).unwrap();
+        assert!(serde_json::from_str::<EncryptedConfigStore>(&content).is_ok());
+        assert!(!content.contains("app-id"));
+
+        drop(store);
+        let store = JsonFileStore::open(dir.path(), key("passphrase"))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_value(Secret::AppId).await.as_deref(),
+            Some("app-id")
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_key_does_not_create_a_backup() {
+        let dir = TempDir::new().unwrap();
+        let store = JsonFileStore::open(dir.path(), key("right")).await.unwrap();
+        store.set_value(Secret::AppId, "app-id").await.unwrap();
+        drop(store);
+        let file_path = dir.path().join(JsonFileStore::FILE_NAME);
+        let content = std::fs::read_to_string(&file_path).unwrap();
+
+        assert!(JsonFileStore::open(dir.path(), key("wrong")).await.is_err());
+        assert!(JsonFileStore::open(dir.path(), None).await.is_err());
+
+        assert!(backups(&dir).is_empty());
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn corrupted_file_creates_a_backup() {
+        let dir = TempDir::new().unwrap();
+        let file_path = dir.path().join(JsonFileStore::FILE_NAME);
+        std::fs::write(&file_path, "{ not json").unwrap();
+
+        assert!(JsonFileStore::open(dir.path(), None).await.is_err());
+
+        let backups = backups(&dir);
+        assert_eq!(backups.len(), 1);
+        assert_eq!(std::fs::read_to_string(&backups[0]).unwrap(), "{ not json");
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use nullnet_liberror::Error;
+
+use crate::storage::{Secret, SecretStore, StoreFuture};
+
+/// Store keeping the secrets in memory only, useful for tests and ephemeral deployments.
+#[derive(Default)]
+pub struct MemoryStore {
+    values: Mutex<HashMap<Secret, String>>,
+}
+
+impl MemoryStore {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn values(&self) -> std::sync::MutexGuard<'_, HashMap<Secret, String>> {
+        self.values
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl SecretStore for MemoryStore {
+    fn get_value(&self, secret: Secret) -> StoreFuture<'_, Option<String>> {
+        let value = self
+            .values()
+            .get(&secret)
+            .filter(|v| !v.is_empty())
+            .cloned();
+        Box::pin(async move { value })
+    }
+
+    fn set_value<'a>(
+        &'a self,
+        secret: Secret,
+        value: &'a str,
+    ) -> StoreFuture<'a, Result<(), Error>> {
+        self.values().insert(secret, value.to_string());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete_value(&self, secret: Secret) -> StoreFuture<'_, Result<(), Error>> {
+        self.values().remove(&secret);
+        Box::pin(async { Ok(()) })
+    }
+}
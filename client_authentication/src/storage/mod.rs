@@ -0,0 +1,59 @@
+mod directory_store;
+mod encryption;
+pub(crate) mod file_utils;
+mod json_file_store;
+mod memory_store;
+
+use std::future::Future;
+use std::pin::Pin;
+
+use serde::{Deserialize, Serialize};
+
+use nullnet_liberror::Error;
+
+pub use directory_store::DirectoryStore;
+pub use encryption::StorageKey;
+pub use json_file_store::JsonFileStore;
+pub use memory_store::MemoryStore;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
+pub enum Secret {
+    InstallationCode,
+    AppId,
+    AppSecret,
+    DeviceUuid,
+}
+
+impl Secret {
+    /// Name of the secret, used as key by the stores.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Secret::InstallationCode => "InstallationCode",
+            Secret::AppId => "AppId",
+            Secret::AppSecret => "AppSecret",
+            Secret::DeviceUuid => "DeviceUuid",
+        }
+    }
+}
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Backend used to persist the client credentials.
+///
+/// Each [`Context`](crate::Context) owns its own store.
+pub trait SecretStore: Send + Sync {
+    /// Returns the value of `secret`, or `None` if it's not set (or empty).
+    fn get_value(&self, secret: Secret) -> StoreFuture<'_, Option<String>>;
+
+    /// Sets the value of `secret`.
+    fn set_value<'a>(
+        &'a self,
+        secret: Secret,
+        value: &'a str,
+    ) -> StoreFuture<'a, Result<(), Error>>;
+
+    /// Removes `secret` from the store.
+    fn delete_value(&self, secret: Secret) -> StoreFuture<'_, Result<(), Error>>;
+}
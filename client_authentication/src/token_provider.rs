@@ -1,17 +1,28 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::RwLock;
-use tokio::time::Instant;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
 
 #[derive(Debug)]
 pub enum RetrievalStrategy {
-    // Immediate,
+    /// Return the current token, if any.
+    Immediate,
+    /// Wait up to the given time for a token to be available.
     Await(Duration),
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct TokenProvider {
-    token: Arc<RwLock<Option<String>>>,
+    token: Arc<watch::Sender<Option<String>>>,
+}
+
+impl Default for TokenProvider {
+    fn default() -> Self {
+        Self {
+            token: Arc::new(watch::Sender::new(None)),
+        }
+    }
 }
 
 impl TokenProvider {
@@ -20,31 +31,103 @@ impl TokenProvider {
     }
 
     pub async fn update(&self, token: impl Into<String>) {
-        let mut lock = self.token.write().await;
-        *lock = Some(token.into());
+        self.token.send_replace(Some(token.into()));
+    }
+
+    pub async fn clear(&self) {
+        self.token.send_replace(None);
     }
 
     pub async fn get(&self) -> Option<String> {
-        self.token.read().await.clone()
+        self.token.borrow().clone()
     }
 
     pub async fn obtain(&self, strategy: RetrievalStrategy) -> Option<String> {
         match strategy {
-            // RetrievalStrategy::Immediate => self.get().await,
+            RetrievalStrategy::Immediate => self.get().await,
             RetrievalStrategy::Await(timeout) => {
-                let deadline = Instant::now() + timeout;
-                loop {
-                    if let Some(token) = self.get().await {
-                        return Some(token);
-                    }
-
-                    if Instant::now() >= deadline {
-                        return None;
-                    }
-
-                    tokio::task::yield_now().await;
-                }
+                let mut receiver = self.token.subscribe();
+                let token = tokio::time::timeout(timeout, receiver.wait_for(Option::is_some))
+                    .await
+                    .ok()?
+                    .ok()?;
+                token.clone()
             }
         }
     }
+
+    /// Returns `true` if a token is available.
+    #[must_use]
+    pub fn has_token(&self) -> bool {
+        self.token.borrow().is_some()
+    }
+
+    /// Returns the expiration time of the current token,
+    /// if it's a JWT carrying an `exp` claim.
+    #[must_use]
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.token.borrow().as_deref().and_then(jwt_expiration)
+    }
+
+    /// Returns a receiver notified every time the token is updated or cleared.
+    #[must_use]
+    pub fn watch(&self) -> watch::Receiver<Option<String>> {
+        self.token.subscribe()
+    }
+}
+
+/// Extracts the `exp` claim from a JWT, without verifying its signature.
+fn jwt_expiration(token: &str) -> Option<SystemTime> {
+    let payload = token.split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+    let exp = claims.get("exp")?.as_u64()?;
+    Some(UNIX_EPOCH + Duration::from_secs(exp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jwt(claims: &str) -> String {
+        format!(
+            "{}.{}.signature",
+            URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#),
+            URL_SAFE_NO_PAD.encode(claims)
+        )
+    }
+
+    #[test]
+    fn expiration_is_read_from_the_exp_claim() {
+        let token = jwt(r#"{"sub":"device","exp":1700000000}"#);
+        assert_eq!(
+            jwt_expiration(&token),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+    }
+
+    #[test]
+    fn padded_payload_is_accepted() {
+        let payload = base64::engine::general_purpose::URL_SAFE.encode(r#"{"exp":10}"#);
+        assert!(payload.ends_with('='));
+        let token = format!("header.{payload}.signature");
+        assert_eq!(
+            jwt_expiration(&token),
+            Some(UNIX_EPOCH + Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn missing_or_invalid_exp_claim_is_ignored() {
+        assert_eq!(jwt_expiration(&jwt(r#"{"sub":"device"}"#)), None);
+        assert_eq!(jwt_expiration(&jwt(r#"{"exp":"1700000000"}"#)), None);
+        assert_eq!(jwt_expiration(&jwt(r#"{"exp":-1}"#)), None);
+    }
+
+    #[test]
+    fn malformed_tokens_are_ignored() {
+        assert_eq!(jwt_expiration("opaque-token"), None);
+        assert_eq!(jwt_expiration("header.not base64!.signature"), None);
+        assert_eq!(jwt_expiration(&jwt("not json")), None);
+    }
 }
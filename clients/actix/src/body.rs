@@ -0,0 +1,99 @@
+use actix_web::body::{BodySize, BodyStream, BoxBody, MessageBody, SizedStream};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::rt::time::{timeout, Instant};
+use actix_web::web::Bytes;
+use actix_web::HttpMessage;
+use futures_util::{stream, Stream, StreamExt};
+use std::time::Duration;
+
+/// Reads up to `limit` bytes of the request payload, for inspection.
+///
+/// The payload is replaced by one replaying the bytes that were read, followed by the rest of the payload,
+/// so that the handler still sees the whole body.
+pub(crate) async fn inspect_body(req: &mut ServiceRequest, limit: usize) -> Option<String> {
+    let mut payload = req.take_payload();
+    let (chunks, error, _) = read_prefix(&mut payload, limit, None).await;
+
+    let captured = concat(&chunks, limit);
+    let replayed = stream::iter(chunks.into_iter().map(Ok).chain(error.map(Err)));
+    req.set_payload(Payload::from(replayed.chain(payload).boxed_local()));
+
+    (!captured.is_empty()).then(|| String::from_utf8_lossy(&captured).into_owned())
+}
+
+/// Reads up to `limit` bytes of the response body, waiting at most `timeout`,
+/// and returns the response with its body intact.
+pub(crate) async fn capture_response_body(
+    resp: ServiceResponse,
+    limit: usize,
+    timeout: Duration,
+) -> (ServiceResponse, Vec<u8>) {
+    let (req, res) = resp.into_parts();
+    let (res, body) = res.into_parts();
+    let size = body.size();
+
+    let mut body = Box::pin(body);
+    let mut data = stream::poll_fn(move |cx| body.as_mut().poll_next(cx));
+    let deadline = Instant::now() + timeout;
+    let (chunks, error, complete) = read_prefix(&mut data, limit, Some(deadline)).await;
+
+    let mut captured = concat(&chunks, usize::MAX);
+    let body = if complete {
+        BoxBody::new(Bytes::from(captured.clone()))
+    } else {
+        let replayed = stream::iter(chunks.into_iter().map(Ok).chain(error.map(Err)));
+        let rest = replayed.chain(data);
+        match size {
+            // keep the announced size of the body
+            BodySize::Sized(size) => BoxBody::new(SizedStream::new(size, rest)),
+            _ => BoxBody::new(BodyStream::new(rest)),
+        }
+    };
+
+    captured.truncate(limit);
+    (ServiceResponse::new(req, res.set_body(body)), captured)
+}
+
+/// Reads chunks until at least `limit` bytes are read, an error occurs, the deadline passes,
+/// or the stream ends (in which case `complete` is `true`).
+async fn read_prefix<S, E>(
+    data: &mut S,
+    limit: usize,
+    deadline: Option<Instant>,
+) -> (Vec<Bytes>, Option<E>, bool)
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    let mut chunks = Vec::new();
+    let mut len = 0;
+    while len < limit {
+        let next = match deadline {
+            Some(deadline) => match timeout(
+                deadline.saturating_duration_since(Instant::now()),
+                data.next(),
+            )
+            .await
+            {
+                Ok(next) => next,
+                // the rest of the body is replayed as it arrives
+                Err(_) => break,
+            },
+            None => data.next().await,
+        };
+        match next {
+            Some(Ok(chunk)) => {
+                len += chunk.len();
+                chunks.push(chunk);
+            }
+            Some(Err(err)) => return (chunks, Some(err), false),
+            None => return (chunks, None, true),
+        }
+    }
+    (chunks, None, false)
+}
+
+fn concat(chunks: &[Bytes], limit: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = chunks.iter().flat_map(|c| c.iter().copied()).collect();
+    bytes.truncate(limit);
+    bytes
+}
@@ -1,8 +1,14 @@
 use std::collections::HashMap;
+use std::net::IpAddr;
 
 use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::http::header::HeaderMap;
-use appguard_client_authentication::CacheKey;
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use appguard_client_authentication::{
+    BypassAction, BypassRequest, CacheKey, CacheKeySource, ClientIpResolver, ClientIpSource,
+    Context, ProxyProtocolAddress, RenderedResponse,
+};
 use nullnet_libappguard::appguard::{
     AppGuardHttpRequest, AppGuardHttpResponse, AppGuardTcpConnection, AppGuardTcpInfo,
 };
@@ -10,15 +16,15 @@ use qstring::QString;
 
 pub(crate) fn to_appguard_tcp_connection(
     req: &ServiceRequest,
+    ctx: &Context,
     token: String,
 ) -> AppGuardTcpConnection {
-    let source_ip = get_source_ip(req);
-    let source_port = req.peer_addr().map(|s| u32::from(s.port()));
+    let (source_ip, source_port) = get_client_addr(req, &ctx.client_ip_resolver);
     let destination = req.app_config().local_addr();
     AppGuardTcpConnection {
         token,
-        source_ip,
-        source_port,
+        source_ip: source_ip.map(|ip| ip.to_string()),
+        source_port: source_port.map(u32::from),
         destination_ip: Some(destination.ip().to_string()),
         destination_port: Some(u32::from(destination.port())),
         protocol: req.connection_info().scheme().to_string(),
@@ -29,6 +35,7 @@ pub(crate) fn to_appguard_http_request(
     req: &ServiceRequest,
     tcp_info: Option<AppGuardTcpInfo>,
     token: String,
+    body: Option<String>,
 ) -> AppGuardHttpRequest {
     let headers = convert_headers(req.headers());
 
@@ -39,7 +46,7 @@ pub(crate) fn to_appguard_http_request(
         original_url: req.path().to_string(),
         headers,
         method: req.method().to_string(),
-        body: None,
+        body,
         query,
         tcp_info,
     }
@@ -60,23 +67,71 @@ pub(crate) fn to_appguard_http_response<B>(
     }
 }
 
-pub(crate) fn to_cache_key(req: &ServiceRequest) -> CacheKey {
+/// Builds the cache key of a request, or returns `None` if it must not be cached.
+///
+/// `body` is the inspected body prefix, if any.
+pub(crate) fn to_cache_key(
+    req: &ServiceRequest,
+    ctx: &Context,
+    body: Option<&str>,
+) -> Option<CacheKey> {
     let headers = convert_headers(req.headers());
     let query: HashMap<String, String> = QString::from(req.query_string()).into_iter().collect();
-    let source_ip = get_source_ip(req);
-    let user_agent = headers
-        .get("user-agent")
-        .unwrap_or(&String::new())
-        .to_string();
+    let source_ip = get_client_addr(req, &ctx.client_ip_resolver)
+        .0
+        .map(|ip| ip.to_string());
 
-    CacheKey {
-        original_url: req.path().to_string(),
-        user_agent,
-        method: req.method().to_string(),
-        body: String::new(),
-        query: query.into_iter().collect(),
-        source_ip: source_ip.unwrap_or_default(),
+    ctx.cache.key_policy().key(&CacheKeySource {
+        source_ip: source_ip.as_deref(),
+        path: req.path(),
+        method: req.method().as_str(),
+        query: &query,
+        headers: &headers,
+        // an empty inspected body is `None`
+        body: ctx
+            .request_body_limit
+            .map(|_| body.unwrap_or_default().as_bytes()),
+    })
+}
+
+pub(crate) fn to_bypass_action(req: &ServiceRequest, ctx: &Context) -> Option<BypassAction> {
+    let source_ip = get_client_addr(req, &ctx.client_ip_resolver)
+        .0
+        .map(|ip| ip.to_string());
+    // forwarded hosts (`Forwarded`, `X-Forwarded-Host`) can only be trusted from a trusted proxy
+    let peer_trusted = req
+        .peer_addr()
+        .is_some_and(|peer| ctx.client_ip_resolver.is_trusted(peer.ip()));
+    let connection_info = req.connection_info();
+    let host = if peer_trusted {
+        Some(connection_info.host())
+    } else {
+        req.headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .or_else(|| req.uri().host())
+    };
+
+    ctx.bypass_action(&BypassRequest {
+        source_ip: source_ip.as_deref(),
+        host,
+        path: req.path(),
+        method: req.method().as_str(),
+    })
+}
+
+pub(crate) fn to_actix_response(rendered: RenderedResponse) -> HttpResponse {
+    let status = StatusCode::from_u16(rendered.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut response = HttpResponse::build(status);
+    for (name, value) in rendered.headers {
+        let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value))
+        else {
+            // invalid headers are skipped rather than failing the whole response
+            continue;
+        };
+        response.append_header((name, value));
     }
+    response.body(rendered.body)
 }
 
 fn convert_headers(headers: &HeaderMap) -> HashMap<String, String> {
@@ -91,8 +146,35 @@ fn convert_headers(headers: &HeaderMap) -> HashMap<String, String> {
         .collect()
 }
 
-fn get_source_ip(req: &ServiceRequest) -> Option<String> {
-    req.connection_info()
-        .realip_remote_addr()
-        .map(std::string::ToString::to_string)
+/// Returns the IP of the client, and its port if it's known (i.e. if the IP wasn't forwarded in a header).
+fn get_client_addr(
+    req: &ServiceRequest,
+    resolver: &ClientIpResolver,
+) -> (Option<IpAddr>, Option<u16>) {
+    let peer = req.peer_addr();
+    let proxy_protocol = req
+        .request()
+        .conn_data::<ProxyProtocolAddress>()
+        .map(|a| a.0);
+    let headers = req.headers();
+    let ip = resolver.resolve(&ClientIpSource {
+        peer: peer.map(|s| s.ip()),
+        proxy_protocol: proxy_protocol.map(|s| s.ip()),
+        forwarded: header_values(headers, "forwarded"),
+        x_forwarded_for: header_values(headers, "x-forwarded-for"),
+        x_real_ip: headers.get("x-real-ip").and_then(|v| v.to_str().ok()),
+    });
+    let port = [proxy_protocol, peer]
+        .into_iter()
+        .flatten()
+        .find(|s| Some(s.ip()) == ip)
+        .map(|s| s.port());
+    (ip, port)
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+    headers
+        .get_all(name)
+        .filter_map(|v| v.to_str().ok())
+        .collect()
 }
@@ -1,16 +1,18 @@
-use std::fmt::{Debug, Display, Formatter};
 use std::future::Future;
 use std::pin::Pin;
 use std::rc::Rc;
 
+use crate::body::{capture_response_body, inspect_body};
 use crate::conversions::{
-    to_appguard_http_request, to_appguard_http_response, to_appguard_tcp_connection, to_cache_key,
+    to_actix_response, to_appguard_http_request, to_appguard_http_response,
+    to_appguard_tcp_connection, to_bypass_action, to_cache_key,
 };
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpResponse, ResponseError,
+    Error,
 };
-use appguard_client_authentication::Context;
+use appguard_client_authentication::{BypassAction, Context, ContextBuilder, ResponseReport};
+use nullnet_libappguard::appguard::AppGuardTcpResponse;
 use nullnet_libappguard::appguard_commands::FirewallPolicy;
 
 #[derive(Clone)]
@@ -19,13 +21,33 @@ pub struct AppGuardMiddleware {
     ctx: Context,
 }
 
+/// Builder used to configure an [`AppGuardMiddleware`] programmatically.
+pub type AppGuardMiddlewareBuilder = ContextBuilder<AppGuardMiddleware>;
+
 impl AppGuardMiddleware {
-    /// Create a new `AppGuard` middleware instance.
+    /// Create a new `AppGuard` middleware instance, configured from the environment.
     #[must_use]
     pub async fn new() -> Option<Self> {
-        let ctx = Context::new(String::from("Actix")).await.ok()?;
+        Self::builder().build().await.ok()
+    }
+
+    /// Return a builder to configure the `AppGuard` middleware programmatically.
+    #[must_use]
+    pub fn builder() -> AppGuardMiddlewareBuilder {
+        AppGuardMiddlewareBuilder::new().client_type("Actix")
+    }
 
-        Some(AppGuardMiddleware { ctx })
+    /// Return the `AppGuard` context used by this middleware
+    /// (e.g. to check whether the client is ready).
+    #[must_use]
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+}
+
+impl From<Context> for AppGuardMiddleware {
+    fn from(ctx: Context) -> Self {
+        AppGuardMiddleware { ctx }
     }
 }
 
@@ -70,17 +92,42 @@ where
     // this service is ready when its next service is ready
     forward_ready!(next_service);
 
-    fn call(&self, req: ServiceRequest) -> Self::Future {
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
         let mut server = self.middleware.ctx.server.clone();
         let ctx = self.middleware.ctx.clone();
         let next_service = self.next_service.clone();
 
         Box::pin(async move {
+            let bypass = to_bypass_action(&req, &ctx);
+            if bypass == Some(BypassAction::Skip) {
+                return next_service.call(req).await;
+            }
+            let monitored = bypass == Some(BypassAction::Monitor);
+
+            if !ctx.is_ready() {
+                let fw_defaults = *ctx.firewall_defaults.lock().await;
+                return if ctx.fallback_policy.resolve(&fw_defaults) == FirewallPolicy::Deny
+                    && ctx.monitor_mode.should_block(monitored, "fallback policy")
+                {
+                    Ok(req.into_response(to_actix_response(ctx.deny_response.render())))
+                } else {
+                    next_service.call(req).await
+                };
+            }
+
+            // the body is part of the cache key
+            let body = match ctx.request_body_limit {
+                Some(limit) => inspect_body(&mut req, limit).await,
+                None => None,
+            };
+
             // first check cache
-            let cache_key = to_cache_key(&req);
-            if let Some(policy) = ctx.cache.lock().await.get(&cache_key) {
-                return if *policy == FirewallPolicy::Deny {
-                    Ok(req.into_response(HttpResponse::Unauthorized().body("Unauthorized")))
+            let cache_key = to_cache_key(&req, &ctx, body.as_deref());
+            if let Some(policy) = cache_key.as_ref().and_then(|key| ctx.cache.get(key)) {
+                return if policy == FirewallPolicy::Deny
+                    && ctx.monitor_mode.should_block(monitored, "cached decision")
+                {
+                    Ok(req.into_response(to_actix_response(ctx.deny_response.render())))
                 } else {
                     next_service.call(req).await
                 };
@@ -91,78 +138,131 @@ where
             let timeout = fw_defaults.timeout;
             let default_policy = FirewallPolicy::try_from(fw_defaults.policy).unwrap_or_default();
 
-            let tcp_info = server
-                .handle_tcp_connection(timeout, to_appguard_tcp_connection(&req, token.clone()))
-                .await
-                .map_err(|e| GrcpError::new(e.message()))?
-                .tcp_info;
+            let tcp_res = ctx
+                .circuit_breaker
+                .call(server.handle_tcp_connection(
+                    timeout,
+                    to_appguard_tcp_connection(&req, &ctx, token.clone()),
+                ))
+                .await;
+            let Some(AppGuardTcpResponse { tcp_info }) = tcp_res else {
+                return if ctx.resolve_failure_policy().await == FirewallPolicy::Deny
+                    && ctx
+                        .monitor_mode
+                        .should_block(monitored, "server unreachable")
+                {
+                    Ok(req.into_response(to_actix_response(ctx.failure_response.render())))
+                } else {
+                    next_service.call(req).await
+                };
+            };
 
-            let request_handler_res = server
-                .handle_http_request(
+            let request_handler_res = ctx
+                .circuit_breaker
+                .call(server.handle_http_request(
                     timeout,
                     default_policy,
-                    to_appguard_http_request(&req, tcp_info.clone(), token.clone()),
-                )
-                .await
-                .map_err(|e| GrcpError::new(e.message()))?;
-
-            let policy = FirewallPolicy::try_from(request_handler_res.policy).unwrap_or_default();
-            if policy == FirewallPolicy::Deny {
-                ctx.cache
-                    .lock()
-                    .await
-                    .insert(cache_key, FirewallPolicy::Deny);
-                return Ok(req.into_response(HttpResponse::Unauthorized().body("Unauthorized")));
+                    to_appguard_http_request(&req, tcp_info.clone(), token.clone(), body),
+                ))
+                .await;
+            let Some(request_handler_res) = request_handler_res else {
+                return if ctx.resolve_failure_policy().await == FirewallPolicy::Deny
+                    && ctx
+                        .monitor_mode
+                        .should_block(monitored, "server unreachable")
+                {
+                    Ok(req.into_response(to_actix_response(ctx.failure_response.render())))
+                } else {
+                    next_service.call(req).await
+                };
+            };
+
+            let request_policy =
+                FirewallPolicy::try_from(request_handler_res.policy).unwrap_or_default();
+            if request_policy == FirewallPolicy::Deny
+                && ctx.monitor_mode.should_block(monitored, "request denied")
+            {
+                if let Some(cache_key) = cache_key {
+                    ctx.cache.insert(cache_key, FirewallPolicy::Deny);
+                }
+                return Ok(req.into_response(to_actix_response(ctx.deny_response.render())));
             }
 
             let fut = next_service.call(req);
 
             let resp: ServiceResponse = fut.await?;
 
-            let response_handler_res = server
-                .handle_http_response(
+            let resp = match &ctx.response_inspection {
+                Some(inspection) => {
+                    let (resp, captured) =
+                        capture_response_body(resp, inspection.limit, inspection.timeout).await;
+                    let status = resp.status().as_u16();
+                    if inspection.inspector.inspect(status, &captured) == FirewallPolicy::Deny
+                        && ctx
+                            .monitor_mode
+                            .should_block(monitored, "response body denied")
+                    {
+                        return Ok(
+                            resp.into_response(to_actix_response(ctx.deny_response.render()))
+                        );
+                    }
+                    resp
+                }
+                None => resp,
+            };
+
+            if let Some(reporter) = &ctx.response_reporter {
+                reporter.report(ResponseReport {
+                    response: to_appguard_http_response(&resp, tcp_info, token),
+                    cache_key,
+                    request_denied: request_policy == FirewallPolicy::Deny,
+                });
+                return Ok(resp);
+            }
+
+            let response_handler_res = ctx
+                .circuit_breaker
+                .call(server.handle_http_response(
                     timeout,
                     default_policy,
                     to_appguard_http_response(&resp, tcp_info, token),
-                )
-                .await
-                .map_err(|e| GrcpError::new(e.message()))?;
-
-            let policy = FirewallPolicy::try_from(response_handler_res.policy).unwrap_or_default();
-            if policy == FirewallPolicy::Deny {
-                ctx.cache
-                    .lock()
-                    .await
-                    .insert(cache_key, FirewallPolicy::Deny);
-                return Ok(resp.into_response(HttpResponse::Unauthorized().body("Unauthorized")));
+                ))
+                .await;
+            let Some(response_handler_res) = response_handler_res else {
+                return if ctx.resolve_failure_policy().await == FirewallPolicy::Deny
+                    && ctx
+                        .monitor_mode
+                        .should_block(monitored, "server unreachable")
+                {
+                    Ok(resp.into_response(to_actix_response(ctx.failure_response.render())))
+                } else {
+                    Ok(resp)
+                };
+            };
+
+            let response_policy =
+                FirewallPolicy::try_from(response_handler_res.policy).unwrap_or_default();
+            if response_policy == FirewallPolicy::Deny
+                && ctx.monitor_mode.should_block(monitored, "response denied")
+            {
+                if let Some(cache_key) = cache_key {
+                    ctx.cache.insert(cache_key, FirewallPolicy::Deny);
+                }
+                return Ok(resp.into_response(to_actix_response(ctx.deny_response.render())));
             }
 
-            ctx.cache
-                .lock()
-                .await
-                .insert(cache_key, FirewallPolicy::Allow);
+            // in monitor-only mode, the verdict is cached even if it wasn't enforced
+            let policy = if request_policy == FirewallPolicy::Deny
+                || response_policy == FirewallPolicy::Deny
+            {
+                FirewallPolicy::Deny
+            } else {
+                FirewallPolicy::Allow
+            };
+            if let Some(cache_key) = cache_key {
+                ctx.cache.insert(cache_key, policy);
+            }
             Ok(resp)
         })
     }
 }
-
-#[derive(Debug)]
-struct GrcpError {
-    message: String,
-}
-
-impl GrcpError {
-    fn new(message: &str) -> Self {
-        GrcpError {
-            message: message.to_string(),
-        }
-    }
-}
-
-impl Display for GrcpError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
-    }
-}
-
-impl ResponseError for GrcpError {}
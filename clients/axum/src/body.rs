@@ -0,0 +1,79 @@
+use axum::body::{Body, Bytes};
+use axum::extract::Request;
+use axum::response::Response;
+use futures_util::{stream, Stream, StreamExt};
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+
+/// Reads up to `limit` bytes of the request body, for inspection.
+///
+/// The body of the returned request replays the bytes that were read, followed by the rest of the body,
+/// so that the handler still sees the whole body.
+pub(crate) async fn inspect_body(req: Request, limit: usize) -> (Request, Option<String>) {
+    let (parts, body) = req.into_parts();
+    let (body, captured) = capture(body, limit, None).await;
+    let inspected = (!captured.is_empty()).then(|| String::from_utf8_lossy(&captured).into_owned());
+    (Request::from_parts(parts, body), inspected)
+}
+
+/// Reads up to `limit` bytes of the response body, waiting at most `timeout`,
+/// and returns the response with its body intact.
+pub(crate) async fn capture_response_body(
+    resp: Response,
+    limit: usize,
+    timeout: Duration,
+) -> (Response, Vec<u8>) {
+    let (parts, body) = resp.into_parts();
+    let (body, captured) = capture(body, limit, Some(Instant::now() + timeout)).await;
+    (Response::from_parts(parts, body), captured)
+}
+
+async fn capture(body: Body, limit: usize, deadline: Option<Instant>) -> (Body, Vec<u8>) {
+    let mut data = body.into_data_stream();
+    let (chunks, error, complete) = read_prefix(&mut data, limit, deadline).await;
+
+    let mut captured: Vec<u8> = chunks.iter().flat_map(|c| c.iter().copied()).collect();
+    let body = if complete {
+        // the whole body was read, so it keeps its exact size
+        Body::from(captured.clone())
+    } else {
+        let replayed = stream::iter(chunks.into_iter().map(Ok).chain(error.map(Err)));
+        Body::from_stream(replayed.chain(data))
+    };
+    captured.truncate(limit);
+
+    (body, captured)
+}
+
+/// Reads chunks until at least `limit` bytes are read, an error occurs, the deadline passes,
+/// or the stream ends (in which case `complete` is `true`).
+async fn read_prefix<S, E>(
+    data: &mut S,
+    limit: usize,
+    deadline: Option<Instant>,
+) -> (Vec<Bytes>, Option<E>, bool)
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    let mut chunks = Vec::new();
+    let mut len = 0;
+    while len < limit {
+        let next = match deadline {
+            Some(deadline) => match timeout_at(deadline, data.next()).await {
+                Ok(next) => next,
+                // the rest of the body is replayed as it arrives
+                Err(_) => break,
+            },
+            None => data.next().await,
+        };
+        match next {
+            Some(Ok(chunk)) => {
+                len += chunk.len();
+                chunks.push(chunk);
+            }
+            Some(Err(err)) => return (chunks, Some(err), false),
+            None => return (chunks, None, true),
+        }
+    }
+    (chunks, None, false)
+}
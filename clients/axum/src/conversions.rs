@@ -1,15 +1,23 @@
-use appguard_client_authentication::CacheKey;
+use appguard_client_authentication::{
+    BypassAction, BypassRequest, CacheKey, CacheKeySource, ClientIpResolver, ClientIpSource,
+    Context, ProxyProtocolAddress, RenderedResponse,
+};
+use axum::body::Body;
 use axum::extract::Request;
-use axum::http::{HeaderMap, Response};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Response, StatusCode};
 use nullnet_libappguard::appguard::{
     AppGuardHttpRequest, AppGuardHttpResponse, AppGuardTcpConnection, AppGuardTcpInfo,
 };
 use qstring::QString;
-use std::collections::{BTreeMap, HashMap};
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 
-pub(crate) fn to_appguard_tcp_connection(req: &Request, token: String) -> AppGuardTcpConnection {
-    let source = get_source_socket(req);
+pub(crate) fn to_appguard_tcp_connection(
+    req: &Request,
+    ctx: &Context,
+    token: String,
+) -> AppGuardTcpConnection {
+    let (source_ip, source_port) = get_client_addr(req, &ctx.client_ip_resolver);
 
     let destination: Option<SocketAddr> = None;
 
@@ -21,8 +29,8 @@ pub(crate) fn to_appguard_tcp_connection(req: &Request, token: String) -> AppGua
 
     AppGuardTcpConnection {
         token,
-        source_ip: source.map(|s| s.ip().to_string()),
-        source_port: source.map(|s| u32::from(s.port())),
+        source_ip: source_ip.map(|ip| ip.to_string()),
+        source_port: source_port.map(u32::from),
         destination_ip: destination.map(|s| s.ip().to_string()),
         destination_port: destination.map(|s| u32::from(s.port())),
         protocol,
@@ -33,6 +41,7 @@ pub(crate) fn to_appguard_http_request(
     req: &Request,
     tcp_info: Option<AppGuardTcpInfo>,
     token: String,
+    body: Option<String>,
 ) -> AppGuardHttpRequest {
     let headers = convert_headers(req.headers());
 
@@ -45,7 +54,7 @@ pub(crate) fn to_appguard_http_request(
         original_url: req.uri().path().to_string(),
         headers,
         method: req.method().to_string(),
-        body: None,
+        body,
         query,
         tcp_info,
     }
@@ -66,25 +75,62 @@ pub(crate) fn to_appguard_http_response<B>(
     }
 }
 
-pub(crate) fn to_cache_key(req: &Request) -> CacheKey {
+/// Builds the cache key of a request, or returns `None` if it must not be cached.
+///
+/// `body` is the inspected body prefix, if any.
+pub(crate) fn to_cache_key(req: &Request, ctx: &Context, body: Option<&str>) -> Option<CacheKey> {
     let headers = convert_headers(req.headers());
-    let query: BTreeMap<String, String> = QString::from(req.uri().query().unwrap_or_default())
+    let query: HashMap<String, String> = QString::from(req.uri().query().unwrap_or_default())
         .into_iter()
         .collect();
-    let source_ip = get_source_socket(req).map(|s| s.ip().to_string());
-    let user_agent = headers
-        .get("user-agent")
-        .unwrap_or(&String::new())
-        .to_string();
+    let source_ip = get_client_addr(req, &ctx.client_ip_resolver)
+        .0
+        .map(|ip| ip.to_string());
 
-    CacheKey {
-        original_url: req.uri().path().to_string(),
-        user_agent,
-        method: req.method().to_string(),
-        body: String::new(),
-        query,
-        source_ip: source_ip.unwrap_or_default(),
+    ctx.cache.key_policy().key(&CacheKeySource {
+        source_ip: source_ip.as_deref(),
+        path: req.uri().path(),
+        method: req.method().as_str(),
+        query: &query,
+        headers: &headers,
+        // an empty inspected body is `None`
+        body: ctx
+            .request_body_limit
+            .map(|_| body.unwrap_or_default().as_bytes()),
+    })
+}
+
+pub(crate) fn to_bypass_action(req: &Request, ctx: &Context) -> Option<BypassAction> {
+    let source_ip = get_client_addr(req, &ctx.client_ip_resolver)
+        .0
+        .map(|ip| ip.to_string());
+    let host = req
+        .headers()
+        .get(axum::http::header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| req.uri().host());
+
+    ctx.bypass_action(&BypassRequest {
+        source_ip: source_ip.as_deref(),
+        host,
+        path: req.uri().path(),
+        method: req.method().as_str(),
+    })
+}
+
+pub(crate) fn to_axum_response(rendered: RenderedResponse) -> Response<Body> {
+    let mut response = Response::new(Body::from(rendered.body));
+    *response.status_mut() =
+        StatusCode::from_u16(rendered.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    for (name, value) in rendered.headers {
+        let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value))
+        else {
+            // invalid headers are skipped rather than failing the whole response
+            continue;
+        };
+        response.headers_mut().append(name, value);
     }
+    response
 }
 
 fn convert_headers(headers: &HeaderMap) -> HashMap<String, String> {
@@ -99,6 +145,34 @@ fn convert_headers(headers: &HeaderMap) -> HashMap<String, String> {
         .collect()
 }
 
+/// Returns the IP of the client, and its port if it's known (i.e. if the IP wasn't forwarded in a header).
+fn get_client_addr(req: &Request, resolver: &ClientIpResolver) -> (Option<IpAddr>, Option<u16>) {
+    let peer = get_source_socket(req);
+    let proxy_protocol = req.extensions().get::<ProxyProtocolAddress>().map(|a| a.0);
+    let headers = req.headers();
+    let ip = resolver.resolve(&ClientIpSource {
+        peer: peer.map(|s| s.ip()),
+        proxy_protocol: proxy_protocol.map(|s| s.ip()),
+        forwarded: header_values(headers, "forwarded"),
+        x_forwarded_for: header_values(headers, "x-forwarded-for"),
+        x_real_ip: headers.get("x-real-ip").and_then(|v| v.to_str().ok()),
+    });
+    let port = [proxy_protocol, peer]
+        .into_iter()
+        .flatten()
+        .find(|s| Some(s.ip()) == ip)
+        .map(|s| s.port());
+    (ip, port)
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect()
+}
+
 fn get_source_socket(req: &Request) -> Option<SocketAddr> {
     req.extensions()
         .get::<axum::extract::ConnectInfo<SocketAddr>>()
@@ -2,17 +2,18 @@ use std::future::Future;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 
-use axum::http::StatusCode;
-use axum::{body::Body, extract::Request, response::Response};
+use axum::{extract::Request, response::Response};
 use nullnet_libappguard::appguard::AppGuardTcpResponse;
 use nullnet_libappguard::appguard_commands::FirewallPolicy;
 use std::task::Poll;
 use tower::{Layer, Service};
 
-use appguard_client_authentication::Context;
+use appguard_client_authentication::{BypassAction, Context, ContextBuilder, ResponseReport};
 
+use crate::body::{capture_response_body, inspect_body};
 use crate::conversions::{
-    to_appguard_http_request, to_appguard_http_response, to_appguard_tcp_connection, to_cache_key,
+    to_appguard_http_request, to_appguard_http_response, to_appguard_tcp_connection,
+    to_axum_response, to_bypass_action, to_cache_key,
 };
 
 #[derive(Clone)]
@@ -21,13 +22,33 @@ pub struct AppGuardMiddleware {
     ctx: Context,
 }
 
+/// Builder used to configure an [`AppGuardMiddleware`] programmatically.
+pub type AppGuardMiddlewareBuilder = ContextBuilder<AppGuardMiddleware>;
+
 impl AppGuardMiddleware {
-    /// Create a new `AppGuard` middleware instance.
+    /// Create a new `AppGuard` middleware instance, configured from the environment.
     #[must_use]
     pub async fn new() -> Option<Self> {
-        let ctx = Context::new(String::from("Axum")).await.ok()?;
+        Self::builder().build().await.ok()
+    }
+
+    /// Return a builder to configure the `AppGuard` middleware programmatically.
+    #[must_use]
+    pub fn builder() -> AppGuardMiddlewareBuilder {
+        AppGuardMiddlewareBuilder::new().client_type("Axum")
+    }
 
-        Some(AppGuardMiddleware { ctx })
+    /// Return the `AppGuard` context used by this middleware
+    /// (e.g. to check whether the client is ready).
+    #[must_use]
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+}
+
+impl From<Context> for AppGuardMiddleware {
+    fn from(ctx: Context) -> Self {
+        AppGuardMiddleware { ctx }
     }
 }
 
@@ -70,11 +91,38 @@ where
         let next_service = self.next_service.clone();
 
         Box::pin(async move {
+            let bypass = to_bypass_action(&req, &ctx);
+            if bypass == Some(BypassAction::Skip) {
+                let fut = next_service.lock().unwrap().call(req);
+                return fut.await;
+            }
+            let monitored = bypass == Some(BypassAction::Monitor);
+
+            if !ctx.is_ready() {
+                let fw_defaults = *ctx.firewall_defaults.lock().await;
+                return if ctx.fallback_policy.resolve(&fw_defaults) == FirewallPolicy::Deny
+                    && ctx.monitor_mode.should_block(monitored, "fallback policy")
+                {
+                    Ok(to_axum_response(ctx.deny_response.render()))
+                } else {
+                    let fut = next_service.lock().unwrap().call(req);
+                    fut.await
+                };
+            }
+
+            // the body is part of the cache key
+            let (req, body) = match ctx.request_body_limit {
+                Some(limit) => inspect_body(req, limit).await,
+                None => (req, None),
+            };
+
             // first check cache
-            let cache_key = to_cache_key(&req);
-            if let Some(policy) = ctx.cache.lock().await.get(&cache_key) {
-                return if *policy == FirewallPolicy::Deny {
-                    Ok(unauthorized_response())
+            let cache_key = to_cache_key(&req, &ctx, body.as_deref());
+            if let Some(policy) = cache_key.as_ref().and_then(|key| ctx.cache.get(key)) {
+                return if policy == FirewallPolicy::Deny
+                    && ctx.monitor_mode.should_block(monitored, "cached decision")
+                {
+                    Ok(to_axum_response(ctx.deny_response.render()))
                 } else {
                     let fut = next_service.lock().unwrap().call(req);
                     fut.await
@@ -86,74 +134,131 @@ where
             let timeout = fw_defaults.timeout;
             let default_policy = FirewallPolicy::try_from(fw_defaults.policy).unwrap_or_default();
 
-            let Ok(AppGuardTcpResponse { tcp_info }) = server
-                .handle_tcp_connection(timeout, to_appguard_tcp_connection(&req, token.clone()))
-                .await
-            else {
-                return Ok(internal_server_error_response());
+            let tcp_res = ctx
+                .circuit_breaker
+                .call(server.handle_tcp_connection(
+                    timeout,
+                    to_appguard_tcp_connection(&req, &ctx, token.clone()),
+                ))
+                .await;
+            let Some(AppGuardTcpResponse { tcp_info }) = tcp_res else {
+                return if ctx.resolve_failure_policy().await == FirewallPolicy::Deny
+                    && ctx
+                        .monitor_mode
+                        .should_block(monitored, "server unreachable")
+                {
+                    Ok(to_axum_response(ctx.failure_response.render()))
+                } else {
+                    let fut = next_service.lock().unwrap().call(req);
+                    fut.await
+                };
             };
 
-            let Ok(request_handler_res) = server
-                .handle_http_request(
+            let request_handler_res = ctx
+                .circuit_breaker
+                .call(server.handle_http_request(
                     timeout,
                     default_policy,
-                    to_appguard_http_request(&req, tcp_info.clone(), token.clone()),
-                )
-                .await
-            else {
-                return Ok(internal_server_error_response());
+                    to_appguard_http_request(&req, tcp_info.clone(), token.clone(), body),
+                ))
+                .await;
+            let Some(request_handler_res) = request_handler_res else {
+                return if ctx.resolve_failure_policy().await == FirewallPolicy::Deny
+                    && ctx
+                        .monitor_mode
+                        .should_block(monitored, "server unreachable")
+                {
+                    Ok(to_axum_response(ctx.failure_response.render()))
+                } else {
+                    let fut = next_service.lock().unwrap().call(req);
+                    fut.await
+                };
             };
 
-            let policy = FirewallPolicy::try_from(request_handler_res.policy).unwrap_or_default();
-            if policy == FirewallPolicy::Deny {
-                ctx.cache
-                    .lock()
-                    .await
-                    .insert(cache_key, FirewallPolicy::Deny);
-                return Ok(unauthorized_response());
+            let request_policy =
+                FirewallPolicy::try_from(request_handler_res.policy).unwrap_or_default();
+            if request_policy == FirewallPolicy::Deny
+                && ctx.monitor_mode.should_block(monitored, "request denied")
+            {
+                if let Some(cache_key) = cache_key {
+                    ctx.cache.insert(cache_key, FirewallPolicy::Deny);
+                }
+                return Ok(to_axum_response(ctx.deny_response.render()));
             }
 
             let fut = next_service.lock().unwrap().call(req);
 
             let resp: Response = fut.await?;
 
-            let Ok(response_handler_res) = server
-                .handle_http_response(
+            let resp = match &ctx.response_inspection {
+                Some(inspection) => {
+                    let (resp, captured) =
+                        capture_response_body(resp, inspection.limit, inspection.timeout).await;
+                    let status = resp.status().as_u16();
+                    if inspection.inspector.inspect(status, &captured) == FirewallPolicy::Deny
+                        && ctx
+                            .monitor_mode
+                            .should_block(monitored, "response body denied")
+                    {
+                        return Ok(to_axum_response(ctx.deny_response.render()));
+                    }
+                    resp
+                }
+                None => resp,
+            };
+
+            if let Some(reporter) = &ctx.response_reporter {
+                reporter.report(ResponseReport {
+                    response: to_appguard_http_response(&resp, tcp_info, token),
+                    cache_key,
+                    request_denied: request_policy == FirewallPolicy::Deny,
+                });
+                return Ok(resp);
+            }
+
+            let response_handler_res = ctx
+                .circuit_breaker
+                .call(server.handle_http_response(
                     timeout,
                     default_policy,
                     to_appguard_http_response(&resp, tcp_info, token),
-                )
-                .await
-            else {
-                return Ok(internal_server_error_response());
+                ))
+                .await;
+            let Some(response_handler_res) = response_handler_res else {
+                return if ctx.resolve_failure_policy().await == FirewallPolicy::Deny
+                    && ctx
+                        .monitor_mode
+                        .should_block(monitored, "server unreachable")
+                {
+                    Ok(to_axum_response(ctx.failure_response.render()))
+                } else {
+                    Ok(resp)
+                };
             };
 
-            let policy = FirewallPolicy::try_from(response_handler_res.policy).unwrap_or_default();
-            if policy == FirewallPolicy::Deny {
-                ctx.cache
-                    .lock()
-                    .await
-                    .insert(cache_key, FirewallPolicy::Deny);
-                return Ok(unauthorized_response());
+            let response_policy =
+                FirewallPolicy::try_from(response_handler_res.policy).unwrap_or_default();
+            if response_policy == FirewallPolicy::Deny
+                && ctx.monitor_mode.should_block(monitored, "response denied")
+            {
+                if let Some(cache_key) = cache_key {
+                    ctx.cache.insert(cache_key, FirewallPolicy::Deny);
+                }
+                return Ok(to_axum_response(ctx.deny_response.render()));
             }
 
-            ctx.cache
-                .lock()
-                .await
-                .insert(cache_key, FirewallPolicy::Allow);
+            // in monitor-only mode, the verdict is cached even if it wasn't enforced
+            let policy = if request_policy == FirewallPolicy::Deny
+                || response_policy == FirewallPolicy::Deny
+            {
+                FirewallPolicy::Deny
+            } else {
+                FirewallPolicy::Allow
+            };
+            if let Some(cache_key) = cache_key {
+                ctx.cache.insert(cache_key, policy);
+            }
             Ok(resp)
         })
     }
 }
-
-fn unauthorized_response() -> Response {
-    let mut response = Response::new(Body::from("Unauthorized"));
-    *response.status_mut() = StatusCode::UNAUTHORIZED;
-    response
-}
-
-fn internal_server_error_response() -> Response {
-    let mut response = Response::new(Body::from("Internal server error"));
-    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-    response
-}
@@ -0,0 +1,31 @@
+use rocket::tokio::io::AsyncReadExt;
+use rocket::Response;
+use std::io::Cursor;
+use std::time::Duration;
+
+/// Reads up to `limit` bytes of the response body, waiting at most `timeout`, and leaves the body intact.
+pub(crate) async fn capture_response_body(
+    resp: &mut Response<'_>,
+    limit: usize,
+    timeout: Duration,
+) -> Vec<u8> {
+    let mut body = resp.body_mut().take();
+    let size = body.size().await;
+
+    let mut captured = Vec::new();
+    // on timeout, the bytes read so far are kept in `captured`
+    let read = rocket::tokio::time::timeout(
+        timeout,
+        AsyncReadExt::take(&mut body, limit as u64).read_to_end(&mut captured),
+    )
+    .await;
+
+    if matches!(read, Ok(Ok(_))) && size.is_some_and(|size| size <= captured.len()) {
+        // the whole body was read, so it keeps its exact size
+        resp.set_sized_body(captured.len(), Cursor::new(captured.clone()));
+    } else {
+        resp.set_streamed_body(AsyncReadExt::chain(Cursor::new(captured.clone()), body));
+    }
+
+    captured
+}
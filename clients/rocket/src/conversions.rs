@@ -1,16 +1,22 @@
-use appguard_client_authentication::CacheKey;
+use appguard_client_authentication::{
+    BypassAction, BypassRequest, CacheKey, CacheKeySource, ClientIpResolver, ClientIpSource,
+    Context, RenderedResponse,
+};
 use nullnet_libappguard::appguard::{
     AppGuardHttpRequest, AppGuardHttpResponse, AppGuardTcpConnection, AppGuardTcpInfo,
 };
 use qstring::QString;
-use rocket::http::HeaderMap;
+use rocket::http::{HeaderMap, Status};
 use rocket::{Request, Response};
-use std::collections::{BTreeMap, HashMap};
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 
-pub(crate) fn to_appguard_tcp_connection(req: &Request, token: String) -> AppGuardTcpConnection {
-    let source_ip = req.client_ip().map(|ip| ip.to_string());
-    let source_port = req.remote().map(|s| u32::from(s.port()));
+pub(crate) fn to_appguard_tcp_connection(
+    req: &Request,
+    ctx: &Context,
+    token: String,
+) -> AppGuardTcpConnection {
+    let (source_ip, source_port) = get_client_addr(req, &ctx.client_ip_resolver);
 
     let destination: Option<SocketAddr> = None;
 
@@ -18,8 +24,8 @@ pub(crate) fn to_appguard_tcp_connection(req: &Request, token: String) -> AppGua
 
     AppGuardTcpConnection {
         token,
-        source_ip,
-        source_port,
+        source_ip: source_ip.map(|ip| ip.to_string()),
+        source_port: source_port.map(u32::from),
         destination_ip: destination.map(|s| s.ip().to_string()),
         destination_port: destination.map(|s| u32::from(s.port())),
         protocol,
@@ -30,6 +36,7 @@ pub(crate) fn to_appguard_http_request(
     req: &Request,
     tcp_info: Option<AppGuardTcpInfo>,
     token: String,
+    body: Option<String>,
 ) -> AppGuardHttpRequest {
     let headers = convert_headers(req.headers());
 
@@ -44,7 +51,7 @@ pub(crate) fn to_appguard_http_request(
         original_url: req.uri().path().to_string(),
         headers,
         method: req.method().to_string(),
-        body: None,
+        body,
         query,
         tcp_info,
     }
@@ -65,27 +72,57 @@ pub(crate) fn to_appguard_http_response(
     }
 }
 
-pub(crate) fn to_cache_key(req: &Request) -> CacheKey {
+/// Builds the cache key of a request, or returns `None` if it must not be cached.
+///
+/// `body` is the inspected body prefix, if any.
+pub(crate) fn to_cache_key(req: &Request, ctx: &Context, body: Option<&str>) -> Option<CacheKey> {
     let headers = convert_headers(req.headers());
-    let query: BTreeMap<String, String> = if let Some(q) = req.uri().query() {
+    let query: HashMap<String, String> = if let Some(q) = req.uri().query() {
         QString::from(q.to_string().as_str()).into_iter().collect()
     } else {
-        BTreeMap::new()
+        HashMap::new()
     };
-    let source_ip = req.client_ip().map(|ip| ip.to_string());
-    let user_agent = headers
-        .get("user-agent")
-        .unwrap_or(&String::new())
-        .to_string();
+    let source_ip = get_client_addr(req, &ctx.client_ip_resolver)
+        .0
+        .map(|ip| ip.to_string());
 
-    CacheKey {
-        original_url: req.uri().path().to_string(),
-        user_agent,
-        method: req.method().to_string(),
-        body: String::new(),
-        query,
-        source_ip: source_ip.unwrap_or_default(),
+    ctx.cache.key_policy().key(&CacheKeySource {
+        source_ip: source_ip.as_deref(),
+        path: req.uri().path().as_str(),
+        method: req.method().as_str(),
+        query: &query,
+        headers: &headers,
+        // an empty inspected body is `None`
+        body: ctx
+            .request_body_limit
+            .map(|_| body.unwrap_or_default().as_bytes()),
+    })
+}
+
+pub(crate) fn to_bypass_action(req: &Request, ctx: &Context) -> Option<BypassAction> {
+    let source_ip = get_client_addr(req, &ctx.client_ip_resolver)
+        .0
+        .map(|ip| ip.to_string());
+
+    ctx.bypass_action(&BypassRequest {
+        source_ip: source_ip.as_deref(),
+        host: req.headers().get_one("Host"),
+        path: req.uri().path().as_str(),
+        method: req.method().as_str(),
+    })
+}
+
+pub(crate) fn to_rocket_response<'r>(rendered: RenderedResponse) -> Response<'r> {
+    let mut response = Response::new();
+    response.set_status(Status::new(rendered.status));
+    for (name, value) in rendered.headers {
+        response.adjoin_raw_header(name, value);
     }
+    response.set_sized_body(
+        rendered.body.len(),
+        std::io::Cursor::new(rendered.body.into_bytes()),
+    );
+    response
 }
 
 fn convert_headers(headers: &HeaderMap) -> HashMap<String, String> {
@@ -99,3 +136,20 @@ fn convert_headers(headers: &HeaderMap) -> HashMap<String, String> {
         })
         .collect()
 }
+
+/// Returns the IP of the client, and its port if it's known (i.e. if the IP wasn't forwarded in a header).
+///
+/// Rocket doesn't expose connection-level data, so PROXY protocol addresses are not supported.
+fn get_client_addr(req: &Request, resolver: &ClientIpResolver) -> (Option<IpAddr>, Option<u16>) {
+    let peer = req.remote();
+    let headers = req.headers();
+    let ip = resolver.resolve(&ClientIpSource {
+        peer: peer.map(|s| s.ip()),
+        proxy_protocol: None,
+        forwarded: headers.get("Forwarded").collect(),
+        x_forwarded_for: headers.get("X-Forwarded-For").collect(),
+        x_real_ip: headers.get_one("X-Real-IP"),
+    });
+    let port = peer.filter(|s| Some(s.ip()) == ip).map(|s| s.port());
+    (ip, port)
+}
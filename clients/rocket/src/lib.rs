@@ -1,4 +1,11 @@
-pub use middleware::AppGuardMiddleware;
+pub use appguard_client_authentication::{
+    BypassRule, CacheConfig, CacheInvalidation, CacheKeyPolicy, CacheSnapshotConfig, CacheStats,
+    CachedDecision, CircuitBreakerConfig, ClientIpResolver, ClientState, Context, DeviceIdentity,
+    DirectoryStore, FallbackPolicy, JsonFileStore, MemoryStore, ResponseBodyInspector,
+    ResponseReportingConfig, ResponseTemplate, SecretStore, StartupMode, StorageKey,
+};
+pub use middleware::{AppGuardMiddleware, AppGuardMiddlewareBuilder};
 
+mod body;
 mod conversions;
 mod middleware;
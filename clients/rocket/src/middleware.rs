@@ -1,11 +1,16 @@
-use rocket::fairing::{Fairing, Info, Kind};
-use rocket::http::Status;
-use rocket::{Data, Request, Response};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::{Method, Status};
+use rocket::route::{BoxFuture, Outcome};
+use rocket::{uri, Build, Data, Request, Response, Rocket, Route};
 
+use crate::body::capture_response_body;
 use crate::conversions::{
-    to_appguard_http_request, to_appguard_http_response, to_appguard_tcp_connection, to_cache_key,
+    to_appguard_http_request, to_appguard_http_response, to_appguard_tcp_connection,
+    to_bypass_action, to_cache_key, to_rocket_response,
+};
+use appguard_client_authentication::{
+    BypassAction, CacheKey, Context, ContextBuilder, RenderedResponse, ResponseReport,
 };
-use appguard_client_authentication::Context;
 use nullnet_libappguard::appguard::AppGuardTcpResponse;
 use nullnet_libappguard::appguard_commands::FirewallPolicy;
 
@@ -14,13 +19,33 @@ pub struct AppGuardMiddleware {
     ctx: Context,
 }
 
+/// Builder used to configure an [`AppGuardMiddleware`] programmatically.
+pub type AppGuardMiddlewareBuilder = ContextBuilder<AppGuardMiddleware>;
+
 impl AppGuardMiddleware {
-    /// Create a new `AppGuard` middleware instance.
+    /// Create a new `AppGuard` middleware instance, configured from the environment.
     #[must_use]
     pub async fn new() -> Option<Self> {
-        let ctx = Context::new(String::from("Rocket")).await.ok()?;
+        Self::builder().build().await.ok()
+    }
 
-        Some(AppGuardMiddleware { ctx })
+    /// Return a builder to configure the `AppGuard` middleware programmatically.
+    #[must_use]
+    pub fn builder() -> AppGuardMiddlewareBuilder {
+        AppGuardMiddlewareBuilder::new().client_type("Rocket")
+    }
+
+    /// Return the `AppGuard` context used by this middleware
+    /// (e.g. to check whether the client is ready).
+    #[must_use]
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+}
+
+impl From<Context> for AppGuardMiddleware {
+    fn from(ctx: Context) -> Self {
+        AppGuardMiddleware { ctx }
     }
 }
 
@@ -29,19 +54,56 @@ impl Fairing for AppGuardMiddleware {
     fn info(&self) -> Info {
         Info {
             name: "AppGuard",
-            kind: Kind::Request | Kind::Response,
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
         }
     }
 
-    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let route = Route::new(Method::Get, REJECTED_ROUTE, rejected_handler);
+        Ok(rocket.mount("/", vec![route]))
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, data: &mut Data<'_>) {
+        let bypass = to_bypass_action(req, &self.ctx);
+        if bypass == Some(BypassAction::Skip) {
+            return;
+        }
+        let monitored = bypass == Some(BypassAction::Monitor);
+
+        if !self.ctx.is_ready() {
+            let fw_defaults = *self.ctx.firewall_defaults.lock().await;
+            if self.ctx.fallback_policy.resolve(&fw_defaults) == FirewallPolicy::Deny
+                && self
+                    .ctx
+                    .monitor_mode
+                    .should_block(monitored, "fallback policy")
+            {
+                reject(req, self.ctx.deny_response.render());
+            }
+            return;
+        }
+
+        // Rocket only allows peeking at the first bytes of the body from a fairing
+        let body = match self.ctx.request_body_limit {
+            Some(limit) => {
+                let peeked = data.peek(limit).await;
+                (!peeked.is_empty()).then(|| String::from_utf8_lossy(peeked).into_owned())
+            }
+            None => None,
+        };
+
         // first check cache
-        let cache_key = to_cache_key(req);
-        if let Some(policy) = self.ctx.cache.lock().await.get(&cache_key) {
-            if *policy == FirewallPolicy::Deny {
-                panic!("Unauthorized");
-            } else {
-                return;
+        let cache_key = to_cache_key(req, &self.ctx, body.as_deref());
+        if let Some(policy) = cache_key.as_ref().and_then(|key| self.ctx.cache.get(key)) {
+            if policy == FirewallPolicy::Deny
+                && self
+                    .ctx
+                    .monitor_mode
+                    .should_block(monitored, "cached decision")
+            {
+                reject(req, self.ctx.deny_response.render());
             }
+            return;
         }
 
         let mut server = self.ctx.server.clone();
@@ -50,41 +112,95 @@ impl Fairing for AppGuardMiddleware {
         let timeout = fw_defaults.timeout;
         let default_policy = FirewallPolicy::try_from(fw_defaults.policy).unwrap_or_default();
 
-        let AppGuardTcpResponse { tcp_info } = server
-            .handle_tcp_connection(timeout, to_appguard_tcp_connection(req, token.clone()))
-            .await
-            .expect("Internal server error");
+        let tcp_res = self
+            .ctx
+            .circuit_breaker
+            .call(server.handle_tcp_connection(
+                timeout,
+                to_appguard_tcp_connection(req, &self.ctx, token.clone()),
+            ))
+            .await;
+        let Some(AppGuardTcpResponse { tcp_info }) = tcp_res else {
+            if self.ctx.resolve_failure_policy().await == FirewallPolicy::Deny
+                && self
+                    .ctx
+                    .monitor_mode
+                    .should_block(monitored, "server unreachable")
+            {
+                reject(req, self.ctx.failure_response.render());
+            }
+            return;
+        };
 
         req.local_cache(|| tcp_info.clone());
 
-        let request_handler_res = server
-            .handle_http_request(
+        let request_handler_res = self
+            .ctx
+            .circuit_breaker
+            .call(server.handle_http_request(
                 timeout,
                 default_policy,
-                to_appguard_http_request(req, tcp_info, token),
-            )
-            .await
-            .expect("Internal server error");
+                to_appguard_http_request(req, tcp_info, token, body),
+            ))
+            .await;
+        let Some(request_handler_res) = request_handler_res else {
+            if self.ctx.resolve_failure_policy().await == FirewallPolicy::Deny
+                && self
+                    .ctx
+                    .monitor_mode
+                    .should_block(monitored, "server unreachable")
+            {
+                reject(req, self.ctx.failure_response.render());
+            }
+            return;
+        };
 
         let policy = FirewallPolicy::try_from(request_handler_res.policy).unwrap_or_default();
-        if policy == FirewallPolicy::Deny {
-            self.ctx
-                .cache
-                .lock()
-                .await
-                .insert(cache_key, FirewallPolicy::Deny);
-            panic!("Unauthorized");
+        let request_denied = policy == FirewallPolicy::Deny;
+        if request_denied
+            && self
+                .ctx
+                .monitor_mode
+                .should_block(monitored, "request denied")
+        {
+            if let Some(cache_key) = cache_key {
+                self.ctx.cache.insert(cache_key, FirewallPolicy::Deny);
+            }
+            reject(req, self.ctx.deny_response.render());
+            return;
         }
+
+        // the verdict of a denied request that wasn't enforced is cached once the response has been reported
+        req.local_cache(|| {
+            Some(Inspected {
+                cache_key,
+                monitored,
+                request_denied,
+            })
+        });
     }
 
     async fn on_response<'r>(&self, req: &'r Request<'_>, resp: &mut Response<'r>) {
-        // first check cache
-        let cache_key = to_cache_key(req);
-        if let Some(policy) = self.ctx.cache.lock().await.get(&cache_key) {
-            if *policy == FirewallPolicy::Deny {
-                *resp = unauthorized_response();
-            }
+        // only the requests checked by the server in `on_request` are left to check
+        let Some(inspected) = req.local_cache(|| None::<Inspected>) else {
             return;
+        };
+        let monitored = inspected.monitored;
+        let request_denied = inspected.request_denied;
+        let cache_key = &inspected.cache_key;
+
+        if let Some(inspection) = &self.ctx.response_inspection {
+            let captured = capture_response_body(resp, inspection.limit, inspection.timeout).await;
+            let status = resp.status().code;
+            if inspection.inspector.inspect(status, &captured) == FirewallPolicy::Deny
+                && self
+                    .ctx
+                    .monitor_mode
+                    .should_block(monitored, "response body denied")
+            {
+                *resp = to_rocket_response(self.ctx.deny_response.render());
+                return;
+            }
         }
 
         let mut server = self.ctx.server.clone();
@@ -95,38 +211,86 @@ impl Fairing for AppGuardMiddleware {
 
         let tcp_info = req.local_cache(|| None);
 
-        let Ok(response_handler_res) = server
-            .handle_http_response(
+        if let Some(reporter) = &self.ctx.response_reporter {
+            reporter.report(ResponseReport {
+                response: to_appguard_http_response(resp, tcp_info.to_owned(), token),
+                cache_key: cache_key.clone(),
+                request_denied,
+            });
+            return;
+        }
+
+        let response_handler_res = self
+            .ctx
+            .circuit_breaker
+            .call(server.handle_http_response(
                 timeout,
                 default_policy,
                 to_appguard_http_response(resp, tcp_info.to_owned(), token),
-            )
-            .await
-        else {
-            *resp = internal_server_error_response();
+            ))
+            .await;
+        let Some(response_handler_res) = response_handler_res else {
+            if self.ctx.resolve_failure_policy().await == FirewallPolicy::Deny
+                && self
+                    .ctx
+                    .monitor_mode
+                    .should_block(monitored, "server unreachable")
+            {
+                *resp = to_rocket_response(self.ctx.failure_response.render());
+            }
             return;
         };
 
         let policy = FirewallPolicy::try_from(response_handler_res.policy).unwrap_or_default();
-        if policy == FirewallPolicy::Deny {
-            *resp = unauthorized_response();
+        if policy == FirewallPolicy::Deny
+            && self
+                .ctx
+                .monitor_mode
+                .should_block(monitored, "response denied")
+        {
+            *resp = to_rocket_response(self.ctx.deny_response.render());
+        }
+        // in monitor-only mode, the verdict is cached even if it wasn't enforced
+        let policy = if request_denied {
+            FirewallPolicy::Deny
+        } else {
+            policy
+        };
+        if let Some(cache_key) = cache_key {
+            self.ctx.cache.insert(cache_key.clone(), policy);
         }
-        self.ctx.cache.lock().await.insert(cache_key, policy);
     }
 }
 
-fn unauthorized_response<'r>() -> Response<'r> {
-    let mut response = Response::new();
-    let body = "Unauthorized";
-    response.set_sized_body(body.len(), std::io::Cursor::new(body));
-    response.set_status(Status::Unauthorized);
-    response
+/// Internal route serving the requests rejected by the fairing.
+const REJECTED_ROUTE: &str = "/__appguard/rejected";
+
+/// Response of a request rejected by the fairing.
+struct Rejection(RenderedResponse);
+
+/// Request checked by the server in `on_request`, whose response is left to check in `on_response`.
+struct Inspected {
+    /// Computed in `on_request`, since it depends on the body.
+    cache_key: Option<CacheKey>,
+    /// Whether the request matched a [`BypassAction::Monitor`] rule.
+    monitored: bool,
+    /// Whether the request was denied, but forwarded in monitor-only mode.
+    request_denied: bool,
+}
+
+/// Short-circuits a request: instead of reaching the user's handler,
+/// it's rerouted to the internal route serving the rejection.
+fn reject(req: &mut Request<'_>, response: RenderedResponse) {
+    req.local_cache(|| Some(Rejection(response)));
+    req.set_method(Method::Get);
+    // same path as `REJECTED_ROUTE`
+    req.set_uri(uri!("/__appguard/rejected"));
 }
 
-fn internal_server_error_response<'r>() -> Response<'r> {
-    let mut response = Response::new();
-    let body = "Internal server error";
-    response.set_sized_body(body.len(), std::io::Cursor::new(body));
-    response.set_status(Status::InternalServerError);
-    response
+fn rejected_handler<'r>(req: &'r Request<'_>, data: Data<'r>) -> BoxFuture<'r> {
+    match req.local_cache(|| None::<Rejection>) {
+        Some(Rejection(response)) => Outcome::Success(to_rocket_response(response.clone())).pin(),
+        // the route was requested directly by a client
+        None => Outcome::forward(data, Status::NotFound).pin(),
+    }
 }